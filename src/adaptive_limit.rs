@@ -0,0 +1,102 @@
+use std::sync::Mutex;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::log_file::log_eprintln;
+
+/// Concurrency limit that shrinks when downloads fail and grows back as they succeed.
+///
+/// Every failure lowers the limit by one, down to `min`. After as many successes in a row as the
+/// current limit, it is raised by one, up to `max`.
+#[derive(Debug)]
+pub(crate) struct AdaptiveLimiter {
+    semaphore: Semaphore,
+    min: usize,
+    max: usize,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: usize,
+    successes_in_a_row: usize,
+    // Permits that have to be taken out of circulation once they are released, because the limit
+    // was lowered while they were in use.
+    debt: usize,
+}
+
+/// Permit to run one download, returned to the limiter on drop.
+pub(crate) struct AdaptivePermit<'a> {
+    permit: Option<SemaphorePermit<'a>>,
+    limiter: &'a AdaptiveLimiter,
+}
+
+impl AdaptiveLimiter {
+    pub(crate) fn new(initial: usize, min: usize, max: usize) -> Self {
+        let max = max.max(min);
+        let initial = initial.clamp(min, max);
+        Self {
+            semaphore: Semaphore::new(initial),
+            min,
+            max,
+            state: Mutex::new(LimiterState {
+                limit: initial,
+                successes_in_a_row: 0,
+                debt: 0,
+            }),
+        }
+    }
+
+    pub(crate) async fn acquire(&self) -> AdaptivePermit<'_> {
+        let permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("The semaphore is never closed");
+        AdaptivePermit {
+            permit: Some(permit),
+            limiter: self,
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.successes_in_a_row += 1;
+        if state.successes_in_a_row >= state.limit && state.limit < self.max {
+            state.successes_in_a_row = 0;
+            state.limit += 1;
+            if state.debt > 0 {
+                state.debt -= 1;
+            } else {
+                self.semaphore.add_permits(1);
+            }
+        }
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.successes_in_a_row = 0;
+        if state.limit > self.min {
+            state.limit -= 1;
+            if self.semaphore.forget_permits(1) == 0 {
+                state.debt += 1;
+            }
+            log_eprintln!(
+                "Lowering the number of concurrent downloads to {}",
+                state.limit
+            );
+        }
+    }
+}
+
+impl Drop for AdaptivePermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        if let Some(permit) = self.permit.take() {
+            if state.debt > 0 {
+                state.debt -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Modpack archive formats. Zip files are the standard, tar archives (optionally gzipped) and
+//! unpacked directories are accepted too.
+
+use std::path::Path;
+
+use async_zip::{error::ZipError, tokio::read::fs::ZipFileReader};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+use crate::{
+    directory::DirectoryArchive,
+    tar::{TarArchive, TarError},
+};
+
+/// File type bits of a unix mode and the value marking a symlink.
+const S_IFMT: u16 = 0o170000;
+const S_IFLNK: u16 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// Anything else an archive can hold, like hard links or device files.
+    Other,
+}
+
+/// An entry of an archive with a UTF-8 name. Entries with other names can't be part of a modpack.
+#[derive(Debug, Clone)]
+pub(crate) struct ArchiveEntry {
+    /// Position among all entries of the archive, used to read the entry.
+    pub index: usize,
+    pub name: String,
+    pub kind: EntryKind,
+    /// Uncompressed size of the content.
+    pub size: u64,
+    /// CRC-32 of the content, for formats that store it.
+    pub crc32: Option<u32>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ArchiveError {
+    #[error(transparent)]
+    Zip(#[from] ZipError),
+    #[error(transparent)]
+    Tar(#[from] TarError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Read access to the entries of a modpack archive. Entries can be read concurrently.
+pub(crate) trait Archive {
+    fn entries(&self) -> Result<Vec<ArchiveEntry>, ArchiveError>;
+
+    /// Writes the content of the entry at `index` to `out`, returning the number of bytes
+    /// written. The content of a symlink is the path it points to. Fails if the content doesn't
+    /// match the checksum stored in the archive, for formats that have one.
+    ///
+    /// Only returns once `out` is flushed, so that errors writing the content aren't lost.
+    async fn copy_entry<W: AsyncWrite + Unpin>(
+        &self,
+        index: usize,
+        out: &mut W,
+    ) -> Result<u64, ArchiveError>;
+
+    /// Reads the whole content of the entry at `index` into `buf`.
+    async fn read_entry(&self, index: usize, buf: &mut Vec<u8>) -> Result<(), ArchiveError> {
+        self.copy_entry(index, buf).await.map(|_| ())
+    }
+}
+
+impl Archive for ZipFileReader {
+    fn entries(&self) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        let mut entries = Vec::new();
+        for (index, entry) in self.file().entries().iter().enumerate() {
+            let Ok(name) = entry.filename().as_str() else {
+                continue;
+            };
+            let is_symlink = entry
+                .unix_permissions()
+                .is_some_and(|mode| mode & S_IFMT == S_IFLNK);
+            let kind = if is_symlink {
+                EntryKind::Symlink
+            } else if entry.dir()? {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            };
+            entries.push(ArchiveEntry {
+                index,
+                name: name.to_string(),
+                kind,
+                size: entry.uncompressed_size(),
+                crc32: Some(entry.crc32()),
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn copy_entry<W: AsyncWrite + Unpin>(
+        &self,
+        index: usize,
+        out: &mut W,
+    ) -> Result<u64, ArchiveError> {
+        let mut reader = self.reader_with_entry(index).await?;
+        let written = tokio::io::copy(&mut (&mut reader).compat(), out).await?;
+        if reader.compute_hash() != reader.entry().crc32() {
+            return Err(ZipError::CRC32CheckError.into());
+        }
+        Ok(written)
+    }
+}
+
+/// A modpack archive of any supported format.
+pub(crate) enum PackArchive {
+    Zip(ZipFileReader),
+    Tar(TarArchive),
+    Directory(DirectoryArchive),
+}
+
+impl PackArchive {
+    /// Opens the archive at `path`, telling the format from its content rather than its extension.
+    pub(crate) async fn open(path: &Path) -> Result<Self, ArchiveError> {
+        if path.is_dir() {
+            return Ok(Self::Directory(DirectoryArchive::open(path).await?));
+        }
+        let mut magic = [0; 4];
+        let mut file = tokio::fs::File::open(path).await?;
+        let read = file.read(&mut magic).await?;
+        if magic[..read].starts_with(b"PK") {
+            Ok(Self::Zip(ZipFileReader::new(path).await?))
+        } else {
+            Ok(Self::Tar(TarArchive::open(path).await?))
+        }
+    }
+}
+
+impl Archive for PackArchive {
+    fn entries(&self) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        match self {
+            Self::Zip(zip) => zip.entries(),
+            Self::Tar(tar) => tar.entries(),
+            Self::Directory(dir) => dir.entries(),
+        }
+    }
+
+    async fn copy_entry<W: AsyncWrite + Unpin>(
+        &self,
+        index: usize,
+        out: &mut W,
+    ) -> Result<u64, ArchiveError> {
+        match self {
+            Self::Zip(zip) => zip.copy_entry(index, out).await,
+            Self::Tar(tar) => tar.copy_entry(index, out).await,
+            Self::Directory(dir) => dir.copy_entry(index, out).await,
+        }
+    }
+
+    async fn read_entry(&self, index: usize, buf: &mut Vec<u8>) -> Result<(), ArchiveError> {
+        match self {
+            Self::Zip(zip) => zip.read_entry(index, buf).await,
+            Self::Tar(tar) => tar.read_entry(index, buf).await,
+            Self::Directory(dir) => dir.read_entry(index, buf).await,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Unpacked modpacks, read straight from a directory.
+
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::archive::{Archive, ArchiveEntry, ArchiveError, EntryKind};
+
+#[derive(Debug)]
+pub(crate) struct DirectoryArchive {
+    entries: Vec<(ArchiveEntry, PathBuf)>,
+}
+
+impl DirectoryArchive {
+    /// Lists everything under `root`, without following symlinks.
+    pub(crate) async fn open(root: &Path) -> Result<Self, ArchiveError> {
+        let mut entries = Vec::new();
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let mut read_dir = tokio::fs::read_dir(&dir).await?;
+            while let Some(dir_entry) = read_dir.next_entry().await? {
+                let path = dir_entry.path();
+                let metadata = tokio::fs::symlink_metadata(&path).await?;
+                let kind = if metadata.is_symlink() {
+                    EntryKind::Symlink
+                } else if metadata.is_dir() {
+                    pending.push(path.clone());
+                    EntryKind::Directory
+                } else if metadata.is_file() {
+                    EntryKind::File
+                } else {
+                    EntryKind::Other
+                };
+                // Same as in archives, names that aren't valid UTF-8 can't be part of a modpack
+                let Some(name) = path
+                    .strip_prefix(root)
+                    .expect("Listed from inside the root")
+                    .components()
+                    .map(|c| c.as_os_str().to_str())
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    continue;
+                };
+                let mut name = name.join("/");
+                if kind == EntryKind::Directory {
+                    name.push('/');
+                }
+                entries.push((
+                    ArchiveEntry {
+                        index: entries.len(),
+                        name,
+                        kind,
+                        size: metadata.len(),
+                        crc32: None,
+                    },
+                    path,
+                ));
+            }
+        }
+        // Parents before their content, like in archives
+        entries.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        for (index, (entry, _)) in entries.iter_mut().enumerate() {
+            entry.index = index;
+        }
+        Ok(Self { entries })
+    }
+}
+
+impl Archive for DirectoryArchive {
+    fn entries(&self) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        Ok(self
+            .entries
+            .iter()
+            .map(|(entry, _)| entry.clone())
+            .collect())
+    }
+
+    async fn copy_entry<W: AsyncWrite + Unpin>(
+        &self,
+        index: usize,
+        out: &mut W,
+    ) -> Result<u64, ArchiveError> {
+        let (entry, path) = &self.entries[index];
+        if entry.kind == EntryKind::Symlink {
+            let target = tokio::fs::read_link(path).await?;
+            let target = target.to_string_lossy();
+            out.write_all(target.as_bytes()).await?;
+            out.flush().await?;
+            return Ok(target.len() as u64);
+        }
+        let mut file = tokio::fs::File::open(path).await?;
+        Ok(tokio::io::copy(&mut file, out).await?)
+    }
+}
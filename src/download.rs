@@ -0,0 +1,730 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use futures_util::{stream::StreamExt, TryStreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use reqwest::{header::LAST_MODIFIED, Client, RequestBuilder, StatusCode};
+use thiserror::Error;
+use tokio::{
+    fs::{create_dir_all, File},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tokio_util::io::StreamReader;
+use url::Url;
+
+use crate::{
+    adaptive_limit::AdaptiveLimiter,
+    display_path,
+    hash_checks::{check_hashes, check_sha1},
+    log_file::{self, log_eprintln, log_println},
+    partial_file::PartialFile,
+    retry_budget::RetryBudget,
+    sanitize_path_check,
+    schemas::ModpackFile,
+    verify::verify_files,
+    UnsafePathError,
+};
+
+/// Host of the primary Modrinth mirror. Files from any other host are pointed out in verbose mode.
+pub(crate) const MODRINTH_CDN: &str = "cdn.modrinth.com";
+
+/// How thoroughly an existing file is checked before skipping its download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SkipCheck {
+    /// Only compare the size.
+    Size,
+    /// Compare the size and the SHA-1 hash.
+    Sha1,
+    /// Compare the size and both the SHA-1 and SHA-512 hashes.
+    Full,
+}
+
+/// Settings shared by all file downloads.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DownloadOptions {
+    pub ignore_hashes: bool,
+    pub max_retries: usize,
+    pub verbose: bool,
+    pub preserve_mtime: bool,
+    pub force: bool,
+    /// Report files that keep failing the hash check instead of failing the whole run.
+    pub keep_going_on_hash_fail: bool,
+    /// Show full paths in messages instead of paths relative to the output directory.
+    pub absolute_paths: bool,
+    /// Skip the hash check after each download, the files are checked with [`verify_deferred`]
+    /// once everything is downloaded.
+    pub defer_hash_check: bool,
+    /// Decide whether an existing file can be kept by its hashes alone, even if its size differs
+    /// from the one in the index.
+    pub trust_hashes: bool,
+    pub skip_check: SkipCheck,
+    /// Most concurrent downloads from a single host, on top of the overall limit.
+    pub per_host_jobs: Option<usize>,
+    /// Longest a single download attempt may take. A slower one is cancelled and counts as a
+    /// failed attempt, so the next mirror is tried.
+    pub file_timeout: Option<Duration>,
+}
+
+/// Outcome of the downloads, as far as they got if the run failed.
+#[derive(Debug, Default)]
+pub(crate) struct DownloadReport {
+    /// Files that were downloaded in this run.
+    pub downloaded: Vec<PathBuf>,
+    /// Files that were already there and kept.
+    pub skipped: Vec<PathBuf>,
+    /// Files that were deleted after failing the hash check on every retry.
+    pub hash_failures: Vec<PathBuf>,
+    /// Files that weren't installed because the run failed before getting to them.
+    pub not_downloaded: Vec<PathBuf>,
+    /// How each download host performed, keyed by host.
+    pub mirror_stats: HashMap<String, MirrorStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MirrorStats {
+    pub files: usize,
+    pub bytes: u64,
+    pub failures: usize,
+}
+
+impl DownloadReport {
+    pub(crate) fn print_mirror_stats(&self) {
+        let mut hosts: Vec<_> = self.mirror_stats.iter().collect();
+        hosts.sort_by(|a, b| a.0.cmp(b.0));
+        log_println!(
+            "{:<32} {:>8} {:>14} {:>8}",
+            "Host",
+            "Files",
+            "Bytes",
+            "Failures"
+        );
+        for (host, stats) in hosts {
+            log_println!(
+                "{:<32} {:>8} {:>14} {:>8}",
+                host,
+                stats.files,
+                stats.bytes,
+                stats.failures
+            );
+        }
+    }
+}
+
+/// The HTTP client for downloads, with the credentials it may send.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    pub client: Client,
+    /// Only sent to GitHub, see [`is_github_url`].
+    pub github_token: Option<String>,
+    /// Each only sent to its own host, and only over https.
+    pub mirror_auth: Vec<MirrorAuth>,
+}
+
+/// Basic auth credentials for a private mirror, given as `<host>=<user>:<password>`.
+///
+/// The host includes the port if the mirror URLs name one, like [`host_key`].
+#[derive(Debug, Clone)]
+pub(crate) struct MirrorAuth {
+    pub host: String,
+    pub user: String,
+    pub password: String,
+}
+
+impl MirrorAuth {
+    /// Whether these credentials are meant for the host of `url`, whether or not they may be sent
+    /// to it.
+    pub(crate) fn matches(&self, url: &Url) -> bool {
+        self.host.eq_ignore_ascii_case(&host_key(url))
+    }
+}
+
+impl FromStr for MirrorAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, credentials) = s
+            .split_once('=')
+            .ok_or("Expected <host>=<user>:<password>")?;
+        // Passwords may contain colons, user names can't
+        let (user, password) = credentials
+            .split_once(':')
+            .ok_or("Expected <user>:<password> after the =")?;
+        if host.is_empty() {
+            return Err("The host is empty".to_string());
+        }
+        Ok(Self {
+            host: host.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+impl HttpClient {
+    /// Starts a request to `url` with the credentials meant for its host.
+    fn get(&self, url: &Url) -> RequestBuilder {
+        let mut request = self.client.get(url.clone());
+        // Redirects to another host, like the storage behind release assets, drop the header again
+        if let Some(token) = self.github_token.as_deref().filter(|_| is_github_url(url)) {
+            request = request.bearer_auth(token);
+        } else if let Some(auth) = self
+            .mirror_auth
+            .iter()
+            .find(|auth| url.scheme() == "https" && auth.matches(url))
+        {
+            request = request.basic_auth(&auth.user, Some(&auth.password));
+        }
+        request
+    }
+}
+
+/// State shared by all file downloads of a single run.
+struct DownloadContext<'a> {
+    http: &'a HttpClient,
+    output_dir: &'a Path,
+    progress_bars: MultiProgress,
+    limiter: &'a AdaptiveLimiter,
+    retry_budget: &'a RetryBudget,
+    options: DownloadOptions,
+    /// Bounds the number of output files open at once.
+    open_files: &'a Semaphore,
+    downloaded: Mutex<Vec<PathBuf>>,
+    skipped: Mutex<Vec<PathBuf>>,
+    hash_failures: Mutex<Vec<PathBuf>>,
+    /// Files given up on because the retry budget ran out.
+    out_of_retries: Mutex<Vec<PathBuf>>,
+    mirror_stats: Mutex<HashMap<String, MirrorStats>>,
+    /// Semaphores of the hosts downloaded from so far, when --per-host-jobs is set.
+    host_limits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl DownloadContext<'_> {
+    fn display_path(&self, path: &Path) -> String {
+        display_path(path, self.output_dir, self.options.absolute_paths)
+    }
+
+    fn record_mirror(&self, url: &Url, update: impl FnOnce(&mut MirrorStats)) {
+        update(
+            self.mirror_stats
+                .lock()
+                .unwrap()
+                .entry(host_key(url))
+                .or_default(),
+        );
+    }
+
+    /// Picks one of the `candidates`, indexes into `urls` in order of preference, to download from
+    /// next. If the downloads per host are limited, this waits until one of their hosts allows
+    /// another download, preferring the first one that already does.
+    async fn acquire_mirror(
+        &self,
+        urls: &[Url],
+        candidates: &[usize],
+    ) -> (usize, Option<OwnedSemaphorePermit>) {
+        let Some(limit) = self.options.per_host_jobs else {
+            return (candidates[0], None);
+        };
+        let semaphores: Vec<_> = {
+            let mut host_limits = self.host_limits.lock().unwrap();
+            candidates
+                .iter()
+                .map(|&mirror| {
+                    host_limits
+                        .entry(host_key(&urls[mirror]))
+                        .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+                        .clone()
+                })
+                .collect()
+        };
+        for (&mirror, semaphore) in candidates.iter().zip(&semaphores) {
+            if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+                return (mirror, Some(permit));
+            }
+        }
+        // Acquiring is cancel safe, so the hosts that lose the race don't lose a permit
+        let waiting = semaphores
+            .into_iter()
+            .map(|semaphore| Box::pin(semaphore.acquire_owned()));
+        let (permit, index, _) = futures::future::select_all(waiting).await;
+        (
+            candidates[index],
+            Some(permit.expect("The semaphore is never closed")),
+        )
+    }
+}
+
+/// Identifies the host of `url` in statistics and limits.
+fn host_key(url: &Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => url.to_string(),
+    }
+}
+
+/// Downloads `files` into `output_dir`. The report of what was downloaded or skipped is returned
+/// even if the run fails.
+pub(crate) async fn download_files(
+    mut files: Vec<ModpackFile>,
+    output_dir: &Path,
+    http: &HttpClient,
+    limiter: &AdaptiveLimiter,
+    retry_budget: &RetryBudget,
+    open_files: &Semaphore,
+    options: DownloadOptions,
+) -> (DownloadReport, Result<(), FileDownloadError>) {
+    // The same file listed twice would be downloaded twice, concurrently into the same path
+    let listed = files.len();
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert((file.path.clone(), file.hashes.sha512)));
+    if files.len() < listed {
+        log_println!(
+            "Collapsed {} duplicate entries in the file list",
+            listed - files.len()
+        );
+    }
+
+    let context = DownloadContext {
+        http,
+        output_dir,
+        progress_bars: MultiProgress::with_draw_target(ProgressDrawTarget::stdout()),
+        limiter,
+        retry_budget,
+        options,
+        open_files,
+        downloaded: Mutex::default(),
+        skipped: Mutex::default(),
+        hash_failures: Mutex::default(),
+        out_of_retries: Mutex::default(),
+        mirror_stats: Mutex::default(),
+        host_limits: Mutex::default(),
+    };
+    let context_ref = &context;
+    let paths: Vec<PathBuf> = files
+        .iter()
+        .map(|file| output_dir.join(&file.path))
+        .collect();
+    let files_stream = futures::stream::iter(files);
+    let result = files_stream
+        .map::<Result<_, FileDownloadError>, _>(Ok)
+        // Files wait for their host before taking one of the limiter's permits, so the number of
+        // concurrent downloads is only limited by the permits
+        .try_for_each_concurrent(None, |file| {
+            let path = output_dir.join(&file.path);
+            let path_check = sanitize_path_check(&path, output_dir);
+            let context = context_ref;
+            async move {
+                path_check?;
+                {
+                    let _permit = context.limiter.acquire().await;
+                    let _file_permit = context
+                        .open_files
+                        .acquire()
+                        .await
+                        .expect("The semaphore is never closed");
+                    if options.force {
+                        if path.is_file() {
+                            tokio::fs::remove_file(&path).await?;
+                        }
+                    } else if is_already_downloaded(&file, &path, options).await? {
+                        let line = format!(
+                            "Skipping {}, it is already downloaded",
+                            context.display_path(&path)
+                        );
+                        log_file::write_line(&line);
+                        let _ = context.progress_bars.println(line);
+                        context.skipped.lock().unwrap().push(path);
+                        return Ok(());
+                    }
+                }
+                let partial = PartialFile::new(&path);
+                let result = download_file(context, &file, &path).await;
+                if result.is_ok() {
+                    partial.keep();
+                    context.downloaded.lock().unwrap().push(path);
+                }
+                match result {
+                    Err(FileDownloadError::HashMismatch(path)) => {
+                        context.hash_failures.lock().unwrap().push(path.clone());
+                        if options.keep_going_on_hash_fail {
+                            Ok(())
+                        } else {
+                            Err(FileDownloadError::HashMismatch(path))
+                        }
+                    }
+                    // The other downloads may still succeed without retrying
+                    Err(FileDownloadError::RetryBudgetExhausted(path)) => {
+                        context.out_of_retries.lock().unwrap().push(path);
+                        Ok(())
+                    }
+                    result => result,
+                }
+            }
+        })
+        .await;
+    let mut report = DownloadReport {
+        downloaded: context.downloaded.into_inner().unwrap(),
+        skipped: context.skipped.into_inner().unwrap(),
+        hash_failures: context.hash_failures.into_inner().unwrap(),
+        not_downloaded: Vec::new(),
+        mirror_stats: context.mirror_stats.into_inner().unwrap(),
+    };
+    let finished: HashSet<&PathBuf> = report
+        .downloaded
+        .iter()
+        .chain(&report.skipped)
+        .chain(&report.hash_failures)
+        .collect();
+    report.not_downloaded = paths
+        .into_iter()
+        .filter(|path| !finished.contains(path))
+        .collect();
+    if result.is_err() {
+        return (report, result);
+    }
+    let mut out_of_retries = context.out_of_retries.into_inner().unwrap();
+    if !out_of_retries.is_empty() {
+        out_of_retries.sort();
+        for path in &out_of_retries {
+            log_eprintln!(
+                "Not downloaded, out of retries: {}",
+                display_path(path, output_dir, options.absolute_paths)
+            );
+        }
+        let error = FileDownloadError::OutOfRetries(out_of_retries.len());
+        return (report, Err(error));
+    }
+    (report, Ok(()))
+}
+
+/// Checks the hashes of files downloaded with `defer_hash_check` in one parallel batch.
+///
+/// Corrupted files are deleted. They fail the run unless `keep_going_on_hash_fail` is set, in which
+/// case they are added to the report's hash failures.
+pub(crate) async fn verify_deferred(
+    files: Vec<ModpackFile>,
+    output_dir: &Path,
+    jobs: usize,
+    options: DownloadOptions,
+    report: &mut DownloadReport,
+) -> Result<(), FileDownloadError> {
+    let verified = verify_files(files, output_dir, jobs, options.absolute_paths).await?;
+    let mut failed = verified.corrupted;
+    failed.extend(verified.missing);
+    for path in &failed {
+        let shown_path = display_path(path, output_dir, options.absolute_paths);
+        log_eprintln!("Hash mismatch for file {shown_path}, deleting it");
+        if path.is_file() {
+            tokio::fs::remove_file(path).await?;
+        }
+    }
+    let failed_count = failed.len();
+    report.hash_failures.extend(failed);
+    if failed_count > 0 && !options.keep_going_on_hash_fail {
+        return Err(FileDownloadError::DeferredHashMismatch(failed_count));
+    }
+    Ok(())
+}
+
+/// Whether the token for GitHub may be sent to `url`. Plain http would leak it, as would any host
+/// not run by GitHub.
+fn is_github_url(url: &Url) -> bool {
+    url.scheme() == "https"
+        && matches!(
+            url.host_str(),
+            Some("github.com" | "api.github.com" | "raw.githubusercontent.com")
+        )
+}
+
+/// Whether `path` already holds a valid copy of `file`, so that downloading it again can be skipped.
+///
+/// Unless `trust_hashes` is set, files with a size other than the one in the index aren't hashed
+/// at all. The size is always compared when it's the only check.
+async fn is_already_downloaded(
+    file: &ModpackFile,
+    path: &Path,
+    options: DownloadOptions,
+) -> std::io::Result<bool> {
+    let ignore_size = options.trust_hashes && options.skip_check != SkipCheck::Size;
+    match tokio::fs::metadata(path).await {
+        // Comparing sizes first avoids hashing files that are obviously different
+        Ok(metadata)
+            if metadata.is_file()
+                && (ignore_size || metadata.len() == u64::from(file.file_size)) =>
+        {
+            match options.skip_check {
+                SkipCheck::Size => Ok(true),
+                SkipCheck::Sha1 => check_sha1(&file.hashes, path).await,
+                SkipCheck::Full => check_hashes(&file.hashes, path).await,
+            }
+        }
+        _ => Ok(false),
+    }
+}
+
+#[derive(Debug, Error)]
+enum FileTryDownloadError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("Request to {url} failed. Status code: {status}; message: {message}")]
+    RequestFailed {
+        url: Url,
+        status: StatusCode,
+        message: String,
+    },
+    #[error("The response was cut off after {actual} of {expected} bytes")]
+    Truncated { expected: u64, actual: u64 },
+    #[error("Timed out after {} seconds", .0.as_secs())]
+    TimedOut(Duration),
+}
+
+async fn try_download_file(
+    http: &HttpClient,
+    url: &Url,
+    path: &Path,
+    bar: &ProgressBar,
+    expected_size: u64,
+    preserve_mtime: bool,
+) -> Result<u64, FileTryDownloadError> {
+    let res = http.get(url).send().await?;
+    let status = res.status();
+    if status.is_success() {
+        // Missing or unparseable headers are ignored, the file then keeps the current time.
+        let last_modified = res
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .filter(|_| preserve_mtime);
+
+        // The response may be compressed, in which case Content-Length is the size of the encoded
+        // body while the bar counts decoded bytes. The size from the index is always decoded.
+        bar.set_length(expected_size);
+        // Not available for compressed responses, which reqwest decodes on the fly
+        let content_length = res.content_length();
+
+        let mut out_file = File::create(path).await?;
+        let stream = res.bytes_stream();
+
+        let stream_reader = StreamReader::new(stream.map_err(std::io::Error::other));
+
+        let mut bar_reader = bar.wrap_async_read(stream_reader);
+
+        let written = tokio::io::copy(&mut bar_reader, &mut out_file).await?;
+        if let Some(content_length) = content_length.filter(|&length| written < length) {
+            return Err(FileTryDownloadError::Truncated {
+                expected: content_length,
+                actual: written,
+            });
+        }
+
+        if let Some(modified) = last_modified {
+            out_file.into_std().await.set_modified(modified)?;
+        }
+
+        Ok(written)
+    } else {
+        Err(FileTryDownloadError::RequestFailed {
+            url: url.clone(),
+            status,
+            message: res.text().await?,
+        })
+    }
+}
+
+/// Prints `line` above the progress bars in verbose mode. It is logged either way, so that the log
+/// file has every detail.
+fn print_verbose(pb: &ProgressBar, verbose: bool, line: String) {
+    log_file::write_line(&line);
+    if verbose {
+        pb.println(line);
+    }
+}
+
+/// Leaves `message` in place of the progress bar and logs it.
+fn finish_bar(pb: &ProgressBar, message: String) {
+    log_file::write_line(&message);
+    pb.finish_with_message(message);
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum FileDownloadError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Every mirror of {0} failed")]
+    AllDownloadsFailed(PathBuf),
+    #[error("Hash mismatch for {0} persisted after all retries")]
+    HashMismatch(PathBuf),
+    #[error("{0} files failed the hash check after downloading")]
+    DeferredHashMismatch(usize),
+    #[error("Ran out of retries for the whole modpack while downloading {0}")]
+    RetryBudgetExhausted(PathBuf),
+    #[error("Ran out of retries for the whole modpack, {0} files were not downloaded")]
+    OutOfRetries(usize),
+    #[error(transparent)]
+    UnsafePath(#[from] UnsafePathError),
+}
+
+async fn download_file(
+    context: &DownloadContext<'_>,
+    file: &ModpackFile,
+    path: &Path,
+) -> Result<(), FileDownloadError> {
+    let options = context.options;
+    let shown_path = context.display_path(path);
+    let urls = &file.downloads;
+    let hashes = (!options.ignore_hashes && !options.defer_hash_check).then_some(&file.hashes);
+    // Only shown once the first download starts, files waiting for their turn don't get a bar
+    let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden())
+        .with_message(format!("Downloading {shown_path}"))
+        .with_style(
+            ProgressStyle::default_bar()
+            .template("{msg}\n{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").expect("Incorrect template provided")
+            .progress_chars("#> ")
+        );
+
+    // The directories will be created in case the parent directory doesn't exist or the parent is
+    // actually a file, which is an error condition and will be reported in the error.
+    if !path.parent().unwrap().is_dir() {
+        create_dir_all(path.parent().unwrap()).await?;
+    }
+
+    // Index of the next url to try. Wraps around the list so that a file failing the hash check
+    // can be re-downloaded from the next mirror.
+    let mut next_url = 0;
+    let mut attempts = 0;
+    let mut hash_failures = 0;
+    // Mirrors that served a file failing the hash check. They likely have a stale or wrong version,
+    // so they are skipped as long as any other mirror is left.
+    let mut bad_mirrors = vec![false; urls.len()];
+    // Mirrors that failed to serve the file at all. Once every url has failed, there's nothing left
+    // to try.
+    let mut failed_mirrors = vec![false; urls.len()];
+
+    loop {
+        if !failed_mirrors.contains(&false) {
+            finish_bar(&pb, format!("Failed to download {shown_path}"));
+            break Err(FileDownloadError::AllDownloadsFailed(path.to_path_buf()));
+        }
+        // Every attempt after the first one is a retry and has to fit into the budget.
+        if attempts > 0 && !context.retry_budget.try_take() {
+            // Whatever the previous attempt left behind is either partial or corrupted
+            if path.is_file() {
+                tokio::fs::remove_file(path).await?;
+            }
+            finish_bar(&pb, format!("Failed to download {shown_path}"));
+            break Err(FileDownloadError::RetryBudgetExhausted(path.to_path_buf()));
+        }
+        // Mirrors in the order they are tried, any of them can be picked if its host is less busy
+        let rotation: Vec<usize> = (0..urls.len())
+            .map(|offset| (next_url + offset) % urls.len())
+            .filter(|&mirror| !failed_mirrors[mirror])
+            .collect();
+        let good: Vec<usize> = rotation
+            .iter()
+            .copied()
+            .filter(|&mirror| !bad_mirrors[mirror])
+            .collect();
+        let candidates = if good.is_empty() { rotation } else { good };
+        // Waiting for a host doesn't hold up files that could be downloaded from other hosts
+        let (mirror, host_permit) = context.acquire_mirror(urls, &candidates).await;
+        let _permit = context.limiter.acquire().await;
+        // A file being downloaded is open until it's hashed, one at a time
+        let _file_permit = context
+            .open_files
+            .acquire()
+            .await
+            .expect("The semaphore is never closed");
+        let url = &urls[mirror];
+        next_url = mirror + 1;
+        if attempts == 0 {
+            context.progress_bars.add(pb.clone());
+        }
+        attempts += 1;
+        pb.reset();
+        print_verbose(
+            &pb,
+            options.verbose,
+            format!("Downloading {shown_path} from {url}"),
+        );
+        let download = try_download_file(
+            context.http,
+            url,
+            path,
+            &pb,
+            file.file_size.into(),
+            options.preserve_mtime,
+        );
+        let result = match options.file_timeout {
+            Some(limit) => tokio::time::timeout(limit, download)
+                .await
+                .unwrap_or(Err(FileTryDownloadError::TimedOut(limit))),
+            None => download.await,
+        };
+        drop(host_permit);
+        match result {
+            // Download succeded, verify the file if needed.
+            Ok(written) => {
+                context.limiter.record_success();
+                let verified = match hashes {
+                    Some(hashes) => check_hashes(hashes, path).await?,
+                    None => true,
+                };
+                if verified {
+                    // Some packs list a wrong size with the right hashes, so a different size is
+                    // only worth a warning. Cut off responses are caught in try_download_file.
+                    if written != u64::from(file.file_size) {
+                        log_eprintln!(
+                            "Warning: {shown_path} is {written} bytes, but the modpack says {}",
+                            file.file_size
+                        );
+                    }
+                    context.record_mirror(url, |stats| {
+                        stats.files += 1;
+                        stats.bytes += written;
+                    });
+                    let host = url.host_str().unwrap_or_default();
+                    let provenance = if host == MODRINTH_CDN {
+                        format!("{shown_path} was served by {host}")
+                    } else {
+                        format!("{shown_path} was served by {host}, which is not the Modrinth CDN")
+                    };
+                    print_verbose(&pb, options.verbose, provenance);
+                    finish_bar(&pb, format!("Downloaded {shown_path} from {url}"));
+                    break Ok(());
+                }
+                context.record_mirror(url, |stats| stats.failures += 1);
+                hash_failures += 1;
+                bad_mirrors[mirror] = true;
+                log_eprintln!("Hash mismatch for file {shown_path} downloaded from {url}");
+                // Every mirror was tried and retries are exhausted, the corrupted file must not
+                // stay in the output. Mirrors that failed outright won't serve anything better.
+                let all_tried = bad_mirrors
+                    .iter()
+                    .zip(&failed_mirrors)
+                    .all(|(&bad, &failed)| bad || failed);
+                if hash_failures > options.max_retries && all_tried {
+                    log_eprintln!("Deleting corrupted file {shown_path}");
+                    tokio::fs::remove_file(path).await?;
+                    finish_bar(&pb, format!("Failed to download {shown_path}"));
+                    break Err(FileDownloadError::HashMismatch(path.to_path_buf()));
+                }
+            }
+            // An error occured. Report and go to the next url.
+            Err(why) => {
+                context.limiter.record_failure();
+                context.record_mirror(url, |stats| stats.failures += 1);
+                failed_mirrors[mirror] = true;
+                log_eprintln!("Failed to download file {shown_path} from {url}: {why}");
+            }
+        }
+    }
+}
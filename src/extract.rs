@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use async_zip::error::ZipError;
+use clap::ValueEnum;
+use futures_util::stream::StreamExt;
+use thiserror::Error;
+use tokio::{
+    fs::{create_dir_all, File},
+    sync::Semaphore,
+};
+
+use crate::{
+    archive::{Archive, ArchiveEntry, ArchiveError, EntryKind},
+    canonicalize_recursively, display_path,
+    hash_checks::crc32_file,
+    log_file::{log_eprintln, log_println},
+    partial_file::PartialFile,
+    sanitize_path_check, UnsafePathError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SymlinkMode {
+    /// Don't extract symlinks, printing a warning for each one.
+    Skip,
+    /// Recreate symlinks, as long as they point inside the output directory.
+    Create,
+}
+
+/// Settings for extracting the override folders.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExtractOptions {
+    pub symlinks: SymlinkMode,
+    /// Show full paths in messages instead of paths relative to the output directory.
+    pub absolute_paths: bool,
+    /// Number of leading path components dropped from every entry inside the override folder.
+    pub strip_components: usize,
+    /// Extract OS metadata files like `.DS_Store` instead of skipping them, see [`is_junk`].
+    pub keep_junk: bool,
+    /// Print which files from a later override folder replace ones from an earlier folder.
+    pub verbose: bool,
+    /// Skip entries that fail to extract, like ones with a bad checksum, instead of failing.
+    pub keep_going: bool,
+    /// Leave files that already have the content of their entry alone, see [`is_unchanged`].
+    pub only_changed: bool,
+    /// Most files written at once.
+    pub jobs: usize,
+}
+
+/// Turns a path from an archive or index into a relative path without `..` components.
+pub(crate) fn sanitize_zip_filename(filename: &str) -> PathBuf {
+    filename
+        .replace('\\', "/")
+        .split('/')
+        .filter(|seg| !matches!(*seg, ".." | ""))
+        .collect()
+}
+
+/// Whether `path` is metadata left behind by macOS or Windows file managers rather than part of
+/// the modpack.
+fn is_junk(path: &Path) -> bool {
+    path.components().any(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        matches!(&*name, ".DS_Store" | "Thumbs.db" | "desktop.ini" | "__MACOSX")
+            // AppleDouble files holding resource forks
+            || name.starts_with("._")
+    })
+}
+
+/// `path` without its first `count` components. Like tar, entries that have nothing left after
+/// stripping are left out, and so is the entry of the override folder itself.
+fn strip_components(path: &Path, count: usize) -> Option<PathBuf> {
+    let stripped: PathBuf = path.components().skip(count).collect();
+    (!stripped.as_os_str().is_empty()).then_some(stripped)
+}
+
+/// Paths relative to the output directory of the files and symlinks that [`extract_folder`]
+/// would extract from `folder_name`.
+pub(crate) fn override_paths(
+    archive: &impl Archive,
+    folder_name: &str,
+    options: ExtractOptions,
+) -> Result<Vec<PathBuf>, ArchiveError> {
+    let prefix = format!("{folder_name}/");
+    Ok(archive
+        .entries()?
+        .into_iter()
+        .filter(|entry| matches!(entry.kind, EntryKind::File | EntryKind::Symlink))
+        .filter_map(|entry| {
+            let relative_path = sanitize_zip_filename(entry.name.strip_prefix(&prefix)?);
+            if !options.keep_junk && is_junk(&relative_path) {
+                return None;
+            }
+            strip_components(&relative_path, options.strip_components)
+        })
+        .collect())
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ExtractError {
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+    #[error(transparent)]
+    UnsafePath(#[from] UnsafePathError),
+    #[error("{} is corrupted in the modpack, its CRC doesn't match. Pass --keep-going-on-extract-fail to skip it", .0.to_string_lossy())]
+    Corrupted(PathBuf),
+    #[error("Failed to extract {}: {source}", path.to_string_lossy())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Extracts the entries in `folder_name` into `output_dir`.
+///
+/// `extracted` maps the paths extracted so far to the folder they came from. Calling this for the
+/// common folder first and the side specific one after lets the specific files win.
+///
+/// Directories and symlinks are created in order, then up to `jobs` files are written at once.
+/// Returns the number of files skipped because they failed to extract, which is only ever non-zero
+/// with `keep_going`.
+pub(crate) async fn extract_folder(
+    archive: &impl Archive,
+    folder_name: &str,
+    output_dir: &Path,
+    open_files: &Semaphore,
+    extracted: &mut HashMap<PathBuf, String>,
+    options: ExtractOptions,
+) -> Result<usize, ExtractError> {
+    let prefix = format!("{folder_name}/");
+    let mut files = Vec::new();
+    for entry in archive.entries()? {
+        if let Some(relative_path) = entry.name.strip_prefix(&prefix) {
+            let relative_path = sanitize_zip_filename(relative_path);
+            if !options.keep_junk && is_junk(&relative_path) {
+                if entry.kind != EntryKind::Directory {
+                    log_println!("Skipping junk file {}", relative_path.to_string_lossy());
+                }
+                continue;
+            }
+            let Some(relative_path) = strip_components(&relative_path, options.strip_components)
+            else {
+                continue;
+            };
+            let zip_path = output_dir.join(relative_path);
+            sanitize_path_check(&zip_path, output_dir)?;
+            let shown_path = display_path(&zip_path, output_dir, options.absolute_paths);
+            let io_context = |source| ExtractError::Io {
+                path: zip_path.clone(),
+                source,
+            };
+            if matches!(entry.kind, EntryKind::File | EntryKind::Symlink) {
+                if let Some(previous) = extracted.insert(zip_path.clone(), folder_name.to_string())
+                {
+                    if options.verbose {
+                        log_println!(
+                            "{shown_path} from {folder_name} replaces the one from {previous}"
+                        );
+                    }
+                    // Written concurrently, so only the last entry of a path in a folder is kept
+                    if previous == folder_name {
+                        files.retain(|(_, path): &(ArchiveEntry, PathBuf)| *path != zip_path);
+                    }
+                }
+            }
+            match entry.kind {
+                EntryKind::Symlink => match options.symlinks {
+                    SymlinkMode::Skip => {
+                        log_eprintln!("Skipping symlink {shown_path}");
+                    }
+                    SymlinkMode::Create => {
+                        // The content of a symlink entry is the path it points to
+                        let mut target = Vec::new();
+                        archive.read_entry(entry.index, &mut target).await?;
+                        let target = String::from_utf8_lossy(&target);
+                        log_println!("Extracting symlink {shown_path} -> {target}");
+                        extract_symlink(&zip_path, &shown_path, Path::new(&*target), output_dir)
+                            .await
+                            .map_err(io_context)?;
+                    }
+                },
+                EntryKind::Directory => {
+                    log_println!("Extracting {shown_path}");
+                    if !zip_path.exists() {
+                        create_dir_all(&zip_path).await.map_err(io_context)?
+                    }
+                }
+                EntryKind::Other => log_eprintln!("Skipping {shown_path}, it's not a regular file"),
+                EntryKind::File => files.push((entry, zip_path)),
+            }
+        }
+    }
+
+    let mut results = futures::stream::iter(files)
+        .map(|(entry, zip_path)| {
+            extract_file(archive, entry, zip_path, output_dir, open_files, options)
+        })
+        .buffer_unordered(options.jobs);
+    let mut failed = 0;
+    while let Some(result) = results.next().await {
+        match result {
+            Err(ExtractFileError { shown_path, error }) if options.keep_going => {
+                log_eprintln!("Failed to extract {shown_path}, skipping it: {error}");
+                failed += 1;
+            }
+            Err(ExtractFileError { error, .. }) => return Err(error),
+            Ok(()) => {}
+        }
+    }
+    Ok(failed)
+}
+
+/// An error extracting a single file, with the path to show for it.
+struct ExtractFileError {
+    shown_path: String,
+    error: ExtractError,
+}
+
+/// Writes the content of the file `entry` to `zip_path`. Nothing is left behind if that fails.
+async fn extract_file(
+    archive: &impl Archive,
+    entry: ArchiveEntry,
+    zip_path: PathBuf,
+    output_dir: &Path,
+    open_files: &Semaphore,
+    options: ExtractOptions,
+) -> Result<(), ExtractFileError> {
+    let shown_path = display_path(&zip_path, output_dir, options.absolute_paths);
+    let io_context = |source| ExtractError::Io {
+        path: zip_path.clone(),
+        source,
+    };
+    let result = async {
+        if options.only_changed
+            && is_unchanged(archive, &entry, &zip_path)
+                .await
+                .map_err(io_context)?
+        {
+            log_println!("Skipping {shown_path}, it is unchanged");
+            return Ok(());
+        }
+        log_println!("Extracting {shown_path}");
+        // Joined onto output_dir, so there's always a parent
+        let parent = zip_path.parent().unwrap();
+        if !parent.is_dir() {
+            create_dir_all(parent).await.map_err(io_context)?
+        }
+        let _file_permit = open_files
+            .acquire()
+            .await
+            .expect("The semaphore is never closed");
+        // The partial file is removed when dropped on failure
+        let partial = PartialFile::new(&zip_path);
+        let mut out_file = File::create(&zip_path).await.map_err(io_context)?;
+        match archive.copy_entry(entry.index, &mut out_file).await {
+            Ok(_) => {
+                partial.keep();
+                Ok(())
+            }
+            Err(ArchiveError::Io(source)) => Err(io_context(source)),
+            Err(ArchiveError::Zip(ZipError::CRC32CheckError)) if !options.keep_going => {
+                Err(ExtractError::Corrupted(zip_path.clone()))
+            }
+            Err(why) => Err(why.into()),
+        }
+    }
+    .await;
+    result.map_err(|error| ExtractFileError { shown_path, error })
+}
+
+/// Whether the file at `path` already has the content of `entry`. The file is compared with the
+/// CRC stored in the archive if there is one, or with the content of the entry otherwise. Entries
+/// that can't be read count as changed, extracting them reports the error.
+async fn is_unchanged(
+    archive: &impl Archive,
+    entry: &ArchiveEntry,
+    path: &Path,
+) -> std::io::Result<bool> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_file() && metadata.len() == entry.size => {}
+        _ => return Ok(false),
+    }
+    match entry.crc32 {
+        Some(crc32) => Ok(crc32_file(path).await? == crc32),
+        None => {
+            let mut content = Vec::new();
+            if archive.read_entry(entry.index, &mut content).await.is_err() {
+                return Ok(false);
+            }
+            Ok(tokio::fs::read(path).await? == content)
+        }
+    }
+}
+
+/// Creates a symlink at `link` pointing to `target`, unless the target would end up outside of
+/// `output_dir`, in which case a warning is printed instead.
+async fn extract_symlink(
+    link: &Path,
+    shown_link: &str,
+    target: &Path,
+    output_dir: &Path,
+) -> std::io::Result<()> {
+    // Joined onto output_dir, so there's always a parent
+    let parent = link.parent().unwrap();
+    let resolved = canonicalize_recursively(&parent.join(target));
+    if target.is_absolute() || !resolved.is_some_and(|path| path.starts_with(output_dir)) {
+        log_eprintln!("Skipping symlink {shown_link} pointing outside of the output directory");
+        return Ok(());
+    }
+    if !parent.is_dir() {
+        create_dir_all(parent).await?;
+    }
+    if link.symlink_metadata().is_ok() {
+        tokio::fs::remove_file(link).await?;
+    }
+    #[cfg(unix)]
+    tokio::fs::symlink(target, link).await?;
+    #[cfg(windows)]
+    tokio::fs::symlink_file(target, link).await?;
+    Ok(())
+}
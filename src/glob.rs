@@ -0,0 +1,35 @@
+//! Shell-style glob patterns for matching paths from the index.
+
+/// Whether `path` matches `pattern`. Patterns without a `/` are matched against the file name
+/// alone, others against the whole path.
+///
+/// `?` matches any character and `*` any number of characters, both within a single path
+/// component. `**` matches any number of characters across components, so `**/` also matches no
+/// components at all.
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let path = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    match_from(&pattern, &path)
+}
+
+fn match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', '/', rest @ ..] => (0..=path.len())
+            .filter(|&i| i == 0 || path[i - 1] == '/')
+            .any(|i| match_from(rest, &path[i..])),
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| match_from(rest, &path[i..])),
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| match_from(rest, &path[i..])),
+        ['?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != '/') && match_from(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && match_from(rest, &path[1..]),
+    }
+}
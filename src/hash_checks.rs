@@ -1,35 +1,127 @@
-use std::path::PathBuf;
+use std::path::Path;
 
+use serde::Serialize;
 use sha1::{Digest, Sha1};
-use sha2::Sha512;
+use sha2::{Sha256, Sha512};
+use thiserror::Error;
 use tokio::{fs::File, io::AsyncReadExt};
 
 use crate::schemas::FileHashes;
 
-pub(crate) async fn check_hashes(hashes: FileHashes, path: PathBuf) {
-    let mut file = File::open(&path).await.unwrap();
-    let mut file_data = Vec::with_capacity(
-        file.metadata()
-            .await
-            .map(|md| md.len() as usize)
-            .unwrap_or(0),
-    );
-    file.read_to_end(&mut file_data).await.unwrap();
-    drop(file);
-    let sha1_passed = check_sha1(&file_data, &hashes.sha1);
-    let sha512_passed = check_sha512(&file_data, &hashes.sha512);
-    if !(sha1_passed && sha512_passed) {
-        eprintln!("Deleting corrupted file {}", path.to_string_lossy());
-        tokio::fs::remove_file(path).await.unwrap()
+/// Returns whether the file at `path` matches both of the expected hashes.
+pub(crate) async fn check_hashes(hashes: &FileHashes, path: &Path) -> std::io::Result<bool> {
+    let mut sha1 = Sha1::new();
+    let mut sha512 = Sha512::new();
+    read_in_chunks(path, |chunk| {
+        sha1.update(chunk);
+        sha512.update(chunk);
+    })
+    .await?;
+    Ok(sha1.finalize().as_slice() == hashes.sha1 && sha512.finalize().as_slice() == hashes.sha512)
+}
+
+/// Returns whether the file at `path` matches the expected SHA-1 hash, which is cheaper than
+/// [`check_hashes`] but less thorough.
+pub(crate) async fn check_sha1(hashes: &FileHashes, path: &Path) -> std::io::Result<bool> {
+    Ok(hash_file::<Sha1>(path).await? == hashes.sha1)
+}
+
+/// CRC-32 of the file at `path`, the checksum zip archives store for their entries.
+pub(crate) async fn crc32_file(path: &Path) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    read_in_chunks(path, |chunk| hasher.update(chunk)).await?;
+    Ok(hasher.finalize())
+}
+
+/// Hashes the file at `path` without loading it into memory all at once.
+pub(crate) async fn hash_file<D: Digest>(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    read_in_chunks(path, |chunk| hasher.update(chunk)).await?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// SHA-1, SHA-256 and SHA-512 hashes of a pack file, hex-encoded, which are the hashes
+/// [`verify_pack`] accepts.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PackHashes {
+    pub sha1: String,
+    pub sha256: String,
+    pub sha512: String,
+}
+
+/// Hashes the pack file at `path` with every algorithm in a single read.
+pub(crate) async fn hash_pack(path: &Path) -> std::io::Result<PackHashes> {
+    let mut sha1 = Sha1::new();
+    let mut sha256 = Sha256::new();
+    let mut sha512 = Sha512::new();
+    read_in_chunks(path, |chunk| {
+        sha1.update(chunk);
+        sha256.update(chunk);
+        sha512.update(chunk);
+    })
+    .await?;
+    Ok(PackHashes {
+        sha1: hex::encode(sha1.finalize()),
+        sha256: hex::encode(sha256.finalize()),
+        sha512: hex::encode(sha512.finalize()),
+    })
+}
+
+async fn read_in_chunks(path: &Path, mut consume: impl FnMut(&[u8])) -> std::io::Result<()> {
+    let mut file = File::open(path).await?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break Ok(());
+        }
+        consume(&buf[..read]);
     }
 }
 
-fn check_sha1(data: &[u8], expected_hash: &[u8; 20]) -> bool {
-    let hash = Sha1::digest(data);
-    hash.as_slice() == expected_hash
+#[derive(Debug, Error)]
+pub(crate) enum PackVerifyError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("{0} is neither a sha1/sha256/sha512 hash nor a path to a checksum file")]
+    InvalidChecksum(String),
+    #[error("Pack checksum mismatch: expected {expected}, got {actual}")]
+    Mismatch { expected: String, actual: String },
+}
+
+/// Checks the pack file against `checksum`, which is either a hex-encoded hash or a path to a
+/// checksum file (such as the output of `sha512sum`). The algorithm is picked by the hash length.
+pub(crate) async fn verify_pack(path: &Path, checksum: &str) -> Result<(), PackVerifyError> {
+    let expected = match parse_hash(checksum) {
+        Some(hash) => hash,
+        None => {
+            let contents = tokio::fs::read_to_string(checksum)
+                .await
+                .map_err(|_| PackVerifyError::InvalidChecksum(checksum.to_string()))?;
+            contents
+                .split_whitespace()
+                .next()
+                .and_then(parse_hash)
+                .ok_or_else(|| PackVerifyError::InvalidChecksum(checksum.to_string()))?
+        }
+    };
+    let actual = match expected.len() {
+        20 => hash_file::<Sha1>(path).await?,
+        32 => hash_file::<Sha256>(path).await?,
+        _ => hash_file::<Sha512>(path).await?,
+    };
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(PackVerifyError::Mismatch {
+            expected: hex::encode(expected),
+            actual: hex::encode(actual),
+        })
+    }
 }
 
-fn check_sha512(data: &[u8], expected_hash: &[u8; 64]) -> bool {
-    let hash = Sha512::digest(data);
-    hash.as_slice() == expected_hash
+fn parse_hash(hash: &str) -> Option<Vec<u8>> {
+    hex::decode(hash)
+        .ok()
+        .filter(|bytes| matches!(bytes.len(), 20 | 32 | 64))
 }
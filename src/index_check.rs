@@ -0,0 +1,155 @@
+//! Structural check of `modrinth.index.json` against the modpack format, to point at the exact
+//! field that is wrong when the index fails to deserialize.
+
+use serde_json::Value;
+use url::Url;
+
+/// A field of the index that doesn't match the format, with its JSON path like
+/// `files[2].hashes.sha1`.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexProblem {
+    pub path: String,
+    pub problem: String,
+}
+
+/// Finds the first field of `index` that doesn't match the modpack format, if any.
+pub(crate) fn find_index_problem(index: &Value) -> Option<IndexProblem> {
+    check_index(index).err()
+}
+
+type Check = Result<(), IndexProblem>;
+
+fn problem(path: &str, problem: impl Into<String>) -> Check {
+    Err(IndexProblem {
+        path: path.to_string(),
+        problem: problem.into(),
+    })
+}
+
+fn check_index(index: &Value) -> Check {
+    let Some(root) = index.as_object() else {
+        return problem("$", "expected an object");
+    };
+    let field = |name: &str| root.get(name);
+    check_integer(field("formatVersion"), "formatVersion", u32::MAX.into())?;
+    check_string(field("game"), "game")?;
+    check_string(field("versionId"), "versionId")?;
+    check_string(field("name"), "name")?;
+    if let Some(summary) = field("summary").filter(|summary| !summary.is_null()) {
+        check_string(Some(summary), "summary")?;
+    }
+
+    let Some(files) = field("files") else {
+        return problem("files", "missing");
+    };
+    let Some(files) = files.as_array() else {
+        return problem("files", "expected an array");
+    };
+    for (i, file) in files.iter().enumerate() {
+        check_file(file, &format!("files[{i}]"))?;
+    }
+
+    let Some(dependencies) = field("dependencies") else {
+        return problem("dependencies", "missing");
+    };
+    let Some(dependencies) = dependencies.as_object() else {
+        return problem("dependencies", "expected an object");
+    };
+    for (id, version) in dependencies {
+        let path = format!("dependencies.{id}");
+        if !matches!(
+            id.as_str(),
+            "minecraft" | "forge" | "fabric-loader" | "quilt-loader"
+        ) {
+            return problem(&path, "unknown dependency");
+        }
+        check_string(Some(version), &path)?;
+    }
+    Ok(())
+}
+
+fn check_file(file: &Value, path: &str) -> Check {
+    let Some(file) = file.as_object() else {
+        return problem(path, "expected an object");
+    };
+    let field = |name: &str| (file.get(name), format!("{path}.{name}"));
+
+    let (value, field_path) = field("path");
+    check_string(value, &field_path)?;
+
+    let (hashes, hashes_path) = field("hashes");
+    let Some(hashes) = hashes else {
+        return problem(&hashes_path, "missing");
+    };
+    let Some(hashes) = hashes.as_object() else {
+        return problem(&hashes_path, "expected an object");
+    };
+    for (algorithm, length) in [("sha1", 40), ("sha512", 128)] {
+        let hash_path = format!("{hashes_path}.{algorithm}");
+        check_string(hashes.get(algorithm), &hash_path)?;
+        let hash = hashes[algorithm].as_str().unwrap_or_default();
+        if hash.len() != length || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return problem(
+                &hash_path,
+                format!("expected {length} hexadecimal characters"),
+            );
+        }
+    }
+
+    let (env, env_path) = field("env");
+    if let Some(env) = env.filter(|env| !env.is_null()) {
+        let Some(env) = env.as_object() else {
+            return problem(&env_path, "expected an object");
+        };
+        for side in ["client", "server"] {
+            let side_path = format!("{env_path}.{side}");
+            check_string(env.get(side), &side_path)?;
+            if !matches!(
+                env[side].as_str(),
+                Some("required" | "optional" | "unsupported")
+            ) {
+                return problem(
+                    &side_path,
+                    "expected \"required\", \"optional\" or \"unsupported\"",
+                );
+            }
+        }
+    }
+
+    let (downloads, downloads_path) = field("downloads");
+    let Some(downloads) = downloads else {
+        return problem(&downloads_path, "missing");
+    };
+    let Some(downloads) = downloads.as_array() else {
+        return problem(&downloads_path, "expected an array");
+    };
+    for (i, url) in downloads.iter().enumerate() {
+        let url_path = format!("{downloads_path}[{i}]");
+        check_string(Some(url), &url_path)?;
+        if let Err(why) = Url::parse(url.as_str().unwrap_or_default()) {
+            return problem(&url_path, format!("invalid URL: {why}"));
+        }
+    }
+
+    let (size, size_path) = field("fileSize");
+    check_integer(size, &size_path, u32::MAX.into())
+}
+
+fn check_string(value: Option<&Value>, path: &str) -> Check {
+    match value {
+        None => problem(path, "missing"),
+        Some(Value::String(_)) => Ok(()),
+        Some(_) => problem(path, "expected a string"),
+    }
+}
+
+fn check_integer(value: Option<&Value>, path: &str, max: u64) -> Check {
+    match value {
+        None => problem(path, "missing"),
+        Some(value) => match value.as_u64() {
+            Some(n) if n <= max => Ok(()),
+            Some(_) => problem(path, format!("must be at most {max}")),
+            None => problem(path, "expected a non-negative integer"),
+        },
+    }
+}
@@ -0,0 +1,66 @@
+//! Copy of everything printed, written to the file given with `--log-file` to attach to bug
+//! reports. Warnings and errors are also kept for the `--summary-json` report.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+/// Every line printed to stderr, for the install summary.
+static ERROR_LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Starts copying every printed line into a new file at `path`.
+pub(crate) fn open(path: &Path) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let _ = LOG_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Appends `line` with a timestamp to the log file, if there is one. Lines can also be logged
+/// without being printed, like details only shown with `--verbose`.
+pub(crate) fn write_line(line: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+    let timestamp = httpdate::fmt_http_date(SystemTime::now());
+    let mut file = file.lock().unwrap();
+    for line in line.lines() {
+        // Failing to log must not fail the install
+        let _ = writeln!(file, "[{timestamp}] {line}");
+    }
+}
+
+/// Like [`write_line`], also keeping the line for [`error_lines`].
+pub(crate) fn write_error_line(line: &str) {
+    ERROR_LINES.lock().unwrap().push(line.to_string());
+    write_line(line);
+}
+
+/// The warnings and errors printed so far.
+pub(crate) fn error_lines() -> Vec<String> {
+    ERROR_LINES.lock().unwrap().clone()
+}
+
+/// Like `println!`, also writing the line to the log file.
+macro_rules! log_println {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{line}");
+        $crate::log_file::write_line(&line);
+    }};
+}
+
+/// Like `eprintln!`, also writing the line to the log file.
+macro_rules! log_eprintln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{line}");
+        $crate::log_file::write_error_line(&line);
+    }};
+}
+
+pub(crate) use {log_eprintln, log_println};
@@ -1,24 +1,61 @@
 use std::{
+    collections::{BTreeMap, HashMap},
+    io::{IsTerminal, Write},
     iter::Iterator,
     num::NonZeroUsize,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
 };
 
-use async_zip::tokio::read::fs::ZipFileReader;
-use clap::Parser;
+use adaptive_limit::AdaptiveLimiter;
+use archive::{Archive, ArchiveEntry, ArchiveError, PackArchive};
+use async_zip::error::ZipError;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use dialoguer::Confirm;
-use futures_util::{stream::StreamExt, TryStreamExt};
-use hash_checks::check_hashes;
-use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
-use reqwest::{Client, StatusCode};
-use schemas::{EnvRequirement, ModpackFile, ModrinthIndex};
+use download::{
+    download_files, verify_deferred, DownloadOptions, DownloadReport, FileDownloadError,
+    HttpClient, MirrorAuth, SkipCheck,
+};
+use extract::{
+    extract_folder, override_paths, sanitize_zip_filename, ExtractError, ExtractOptions,
+    SymlinkMode,
+};
+use glob::glob_match;
+use hash_checks::{hash_pack, verify_pack, PackVerifyError};
+use index_check::{find_index_problem, IndexProblem};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log_file::{log_eprintln, log_println};
+use retry_budget::RetryBudget;
+use schemas::{
+    EnvRequirement, FileEnv, ModpackFile, ModrinthIndex, OptionalSelection, SelectionError,
+    SUPPORTED_FORMAT_VERSION,
+};
+use summary::InstallSummary;
 use thiserror::Error;
-use tokio::fs::{create_dir_all, File};
-use tokio_util::{compat::FuturesAsyncReadCompatExt, io::StreamReader};
+use tokio::{fs::create_dir_all, sync::Semaphore};
+use upstream::{verify_upstream, UpstreamError, MODRINTH_API};
 use url::Url;
+use validate::validate_pack;
+use verify::verify_files;
 
+mod adaptive_limit;
+mod archive;
+mod directory;
+mod download;
+mod extract;
+mod glob;
 mod hash_checks;
+mod index_check;
+mod log_file;
+mod partial_file;
+mod retry_budget;
 mod schemas;
+mod summary;
+mod tar;
+mod upstream;
+mod validate;
+mod verify;
 
 const ALLOWED_HOSTS: [&str; 4] = [
     "cdn.modrinth.com",
@@ -28,260 +65,599 @@ const ALLOWED_HOSTS: [&str; 4] = [
 ];
 
 #[derive(Debug, Clone, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 struct CliParameters {
-    input_file: PathBuf,
-    output_dir: PathBuf,
-    /// Download the modpack as server version.
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The modpack to install. The extension doesn't matter, `.zip` files work as well as
+    /// `.mrpack` ones, and so do tar archives, gzipped or not, and unpacked modpack directories.
+    #[arg(required = true)]
+    input_file: Option<PathBuf>,
+    /// Directory to install the modpack into.
+    ///
+    /// A leading `~` and environment variables like `$HOME` or `${PACK}` are expanded, for
+    /// templated paths in scripts.
+    #[arg(required_unless_present = "temp_output")]
+    output_dir: Option<PathBuf>,
+    /// Install into a new directory in the system's temporary directory instead of an output
+    /// directory, and print its path at the end. The directory is kept, so that the installed
+    /// pack can be looked at.
+    #[arg(long, conflicts_with = "output_dir")]
+    temp_output: bool,
+    /// Download the modpack as server version. Shorthand for `--target server`.
+    #[arg(short, long, conflicts_with = "target")]
     server: bool,
+    /// Which side to install the modpack for.
+    ///
+    /// `both` installs every file needed by either side and extracts `overrides`, then
+    /// `overrides-server`, then `overrides-client`. A file present in more than one of them ends
+    /// up with the content from the one extracted last.
+    #[arg(long, value_enum, default_value_t = Target::Client)]
+    target: Target,
+    /// Leave out resource packs and shaders when installing a server, even if the pack installs
+    /// them there. Without this they are only warned about.
+    #[arg(long)]
+    skip_client_content: bool,
     /// If enabled, hash checking stage will be skipped.
     #[arg(short, long)]
     ignore_hashes: bool,
     /// Set the number of concurrent downloads.
+    ///
+    /// This is the starting point, the number is lowered when downloads fail and raised back
+    /// as they succeed, within the bounds of --min-jobs and --max-jobs.
     #[arg(short, long, default_value_t = unsafe {NonZeroUsize::new_unchecked(5)})]
     jobs: NonZeroUsize,
+    /// Lowest number of concurrent downloads to fall back to when downloads fail.
+    #[arg(long, default_value_t = NonZeroUsize::MIN)]
+    min_jobs: NonZeroUsize,
+    /// Highest number of concurrent downloads. Defaults to the value of --jobs.
+    #[arg(long)]
+    max_jobs: Option<NonZeroUsize>,
+    /// Most concurrent downloads from a single host, to go easy on small mirrors.
+    #[arg(long)]
+    per_host_jobs: Option<NonZeroUsize>,
+    /// Number of files hashed at once by --verify and --defer-hash-check. Hashing is bound by the
+    /// CPU rather than the network, so this defaults to the number of CPUs.
+    #[arg(long)]
+    verify_jobs: Option<NonZeroUsize>,
+    /// Number of override files extracted at once. Extracting is bound by the disk, which is why
+    /// the default is low enough to not thrash spinning disks.
+    #[arg(long, default_value_t = unsafe {NonZeroUsize::new_unchecked(4)})]
+    extract_jobs: NonZeroUsize,
+    /// Most output files to have open at once while downloading, verifying and extracting.
+    ///
+    /// Lower it on systems with a low limit of open file descriptors. Work waits for a free slot
+    /// instead of failing.
+    #[arg(long, default_value_t = unsafe {NonZeroUsize::new_unchecked(128)})]
+    max_open_files: NonZeroUsize,
     /// Skip download host check.
     ///
     /// See https://docs.modrinth.com/modpacks/format#downloads
     #[arg(long)]
     skip_host_check: bool,
+    /// When to reject a file because of its download hosts.
+    #[arg(long, value_enum, default_value_t = HostCheck::Any)]
+    host_check: HostCheck,
+    /// List the disallowed download hosts and ask whether to download from them anyway, instead
+    /// of failing right away. Without a terminal to ask in, the check fails as usual.
+    #[arg(long, conflicts_with = "skip_host_check")]
+    confirm_hosts: bool,
+    /// Maximum number of times a file is re-downloaded after failing the hash check.
+    ///
+    /// Retries skip the download urls that already served a file failing the check, and every
+    /// url of the file is tried before giving up, even if that takes more retries.
+    #[arg(long, default_value_t = 3)]
+    max_retries: usize,
+    /// Verify the modpack file against a checksum before doing anything else.
+    ///
+    /// Accepts either a sha1/sha256/sha512 hash in hex or a path to a checksum file, such as the
+    /// output of `sha512sum`.
+    #[arg(long, value_name = "HASH")]
+    verify_pack: Option<String>,
+    /// Check the files already present in the output directory instead of downloading.
+    ///
+    /// Exits with a non-zero code if any file is missing or corrupted.
+    #[arg(long)]
+    verify: bool,
+    /// Look up every file with the Modrinth API before downloading, failing if a file that is
+    /// downloaded from the Modrinth CDN doesn't match any published file. Catches modpacks whose
+    /// index was tampered with, at the cost of one API request per file.
+    #[arg(long)]
+    verify_upstream: bool,
+    /// Base URL of the Modrinth API used by --verify-upstream.
+    #[arg(long, value_name = "URL", default_value = MODRINTH_API, hide = true)]
+    modrinth_api: Url,
+    /// Only download files with the given path or file name. Can be specified multiple times.
+    ///
+    /// Useful for retrying a single problematic download.
+    #[arg(long, value_name = "PATH")]
+    only: Vec<PathBuf>,
+    /// Don't download any files, only extract the overrides. Useful for applying the configs of a
+    /// pack to an existing install.
+    #[arg(
+        long,
+        conflicts_with_all = ["only", "verify", "selection", "save_selection", "print_urls", "emit_aria2"]
+    )]
+    only_overrides: bool,
+    /// Install files matching this glob even if the chosen side doesn't need them. Can be
+    /// specified multiple times.
+    ///
+    /// Patterns without a `/` match file names, others whole paths. `*` and `?` stay within one
+    /// folder, `**` crosses folders.
+    #[arg(long, value_name = "GLOB")]
+    force_include: Vec<String>,
+    /// Never install files matching this glob, even if the chosen side needs them. Wins over
+    /// --force-include. Can be specified multiple times.
+    #[arg(long, value_name = "GLOB")]
+    force_exclude: Vec<String>,
+    /// Print the download URLs of all files the chosen side may need, including optional ones,
+    /// instead of downloading them. Every mirror of a file is printed.
+    #[arg(long)]
+    print_urls: bool,
+    /// With --print-urls, print a JSON object mapping each file path to its URLs.
+    #[arg(long, requires = "print_urls")]
+    json: bool,
+    /// Write an input file for aria2c to FILE instead of downloading, with the mirrors, output
+    /// path and sha512 hash of every file the chosen side may need. Overrides are not included,
+    /// run with --only-overrides afterwards to extract them.
+    #[arg(long, value_name = "FILE", conflicts_with = "print_urls")]
+    emit_aria2: Option<PathBuf>,
+    /// Print more details, such as every url a file is downloaded from and statistics of each
+    /// download host.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Set the modification time of downloaded files to the one reported by the server.
+    #[arg(long)]
+    preserve_mtime: bool,
+    /// Maximum number of retries for the whole modpack, across all files.
+    ///
+    /// Once it's used up, failing files are not retried anymore.
+    #[arg(long)]
+    max_total_retries: Option<usize>,
+    /// Stop retrying failed downloads this many seconds after the downloads have started.
+    #[arg(long, value_name = "SECS")]
+    deadline: Option<u64>,
+    /// Re-download every file, even the ones that are already present and valid.
+    ///
+    /// By default, files that already exist in the output directory and match their hashes are
+    /// skipped.
+    #[arg(long, alias = "overwrite-existing")]
+    force: bool,
+    /// Don't ask anything: optional files are downloaded and the download starts right away.
+    ///
+    /// Enabled automatically when stdin is not a terminal, such as in scripts and pipelines.
+    #[arg(short = 'y', long, visible_alias = "assume-yes")]
+    unattended: bool,
+    /// Maximum number of files a modpack can list before asking for confirmation.
+    #[arg(long, default_value_t = 10_000)]
+    max_files: usize,
+    /// Maximum total size of the files a modpack can list before asking for confirmation.
+    ///
+    /// Accepts a number of bytes with an optional K, M, G or T suffix.
+    #[arg(long, default_value = "32G", value_parser = parse_size)]
+    max_total_size: u64,
+    /// Skip files larger than this, to download them manually later. They are listed at the end.
+    ///
+    /// Accepts a number of bytes with an optional K, M, G or T suffix.
+    #[arg(long, value_parser = parse_size)]
+    max_file_size: Option<u64>,
+    /// Install into a `<name>-<version>` subfolder of the output directory, named after the
+    /// modpack.
+    #[arg(long)]
+    name_subfolder: bool,
+    /// Keep going when a file fails the hash check on every retry.
+    ///
+    /// The file is deleted and reported at the end. By default, such a file fails the whole run.
+    #[arg(long)]
+    keep_going_on_hash_fail: bool,
+    /// Keep going when an override file can't be extracted, for example because it is corrupted
+    /// in the archive.
+    ///
+    /// The file is skipped and the number of such files is reported at the end. By default, such
+    /// a file fails the whole run.
+    #[arg(long)]
+    keep_going_on_extract_fail: bool,
+    /// Only write override files whose content differs from the file already in the output
+    /// directory. Speeds up repeated installs of a pack that is being worked on.
+    #[arg(long)]
+    only_changed_overrides: bool,
+    /// What to do with symlinks found in the overrides.
+    ///
+    /// Created symlinks must point inside the output directory, others are skipped.
+    #[arg(long, value_enum, default_value_t = SymlinkMode::Skip)]
+    symlinks: SymlinkMode,
+    /// Decide which optional files to download using a selection file instead of asking.
+    ///
+    /// The file is JSON in the form of `{"include": ["mods/optional.jar"]}`, listing the paths
+    /// of the optional files to download, as written by --save-selection.
+    #[arg(long, value_name = "FILE")]
+    selection: Option<PathBuf>,
+    /// Save which optional files were chosen to a selection file, for use with --selection.
+    #[arg(long, value_name = "FILE")]
+    save_selection: Option<PathBuf>,
+    /// Show full paths in messages instead of paths relative to the output directory.
+    #[arg(long)]
+    absolute_paths: bool,
+    /// Give up on the whole run after this many seconds, exiting with code 124.
+    ///
+    /// Files that are only partially written at that point are deleted.
+    #[arg(long, value_name = "SECS")]
+    global_timeout: Option<u64>,
+    /// Cancel a download attempt of a single file after this many seconds and try the next
+    /// mirror, so that one slow file can't hold a download slot for the whole run.
+    #[arg(long, value_name = "SECS")]
+    file_timeout: Option<u64>,
+    /// Also write everything that is printed to this file, with timestamps, for attaching to bug
+    /// reports. Details only printed with --verbose are always written.
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+    /// Write a JSON report of the install to this file once it's done: the status of every file,
+    /// the extracted overrides, bytes downloaded, duration and all warnings.
+    ///
+    /// A failed install is reported as well, with its error and the files that weren't installed.
+    #[arg(long, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+    /// Shell command to run in the output directory after a successful install.
+    ///
+    /// WARNING: this runs an arbitrary command with your permissions. The pack name and version
+    /// are passed in the `MRPACK_NAME` and `MRPACK_VERSION` environment variables, and the output
+    /// directory in `MRPACK_OUTPUT_DIR`.
+    #[arg(long, value_name = "CMD")]
+    post_hook: Option<String>,
+    /// Download everything first and check the hashes afterwards in one parallel batch, instead
+    /// of checking each file right after it's downloaded.
+    ///
+    /// Files failing the deferred check are deleted, not downloaded again.
+    #[arg(long, conflicts_with = "ignore_hashes")]
+    defer_hash_check: bool,
+    /// Token sent with downloads from GitHub to raise the rate limit. Never sent to other hosts.
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    github_token: Option<String>,
+    /// Basic auth credentials for a private mirror, as `<host>=<user>:<password>`. Only sent to
+    /// that host, and never over plain http. Mirrors on a non-default port are given as
+    /// `<host>:<port>`. Can be given multiple times.
+    #[arg(long, value_name = "HOST=USER:PASSWORD")]
+    mirror_auth: Vec<MirrorAuth>,
+    /// Additional root certificate to trust for downloads, in PEM or DER format. For mirrors
+    /// using a private CA or TLS-intercepting proxies.
+    #[arg(long, value_name = "FILE")]
+    ca_cert: Option<PathBuf>,
+    /// Don't check TLS certificates at all. Only meant for testing, since it allows anyone on the
+    /// network to tamper with downloads.
+    #[arg(long)]
+    danger_accept_invalid_certs: bool,
+    /// Drop this many leading path components from the override files, like tar's option of the
+    /// same name. Useful for packs that nest their overrides in an extra folder.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    strip_components: usize,
+    /// Fail if two installed files differ only in case, as they overwrite each other on
+    /// case-insensitive filesystems. On by default on Windows and macOS, elsewhere such files are
+    /// only warned about.
+    #[arg(
+        long,
+        value_name = "BOOL",
+        default_value_t = cfg!(any(windows, target_os = "macos")),
+        action = ArgAction::Set
+    )]
+    case_sensitive_check: bool,
+    /// Extract files left behind by macOS and Windows, like `.DS_Store`, `Thumbs.db` and
+    /// `__MACOSX/`, which are skipped by default.
+    #[arg(long)]
+    keep_junk: bool,
+    /// Keep existing files whose hashes match the index even if their size doesn't, for packs
+    /// with wrong file sizes.
+    #[arg(long)]
+    trust_hashes: bool,
+    /// How thoroughly existing files are checked before their download is skipped. Cheaper
+    /// checks speed up re-running installs of large packs. Downloaded files are always checked
+    /// with both hashes.
+    #[arg(long, value_enum, default_value_t = SkipCheck::Full)]
+    skip_check: SkipCheck,
 }
 
-#[derive(Debug, Error)]
-enum IndexReadError {
-    #[error(transparent)]
-    AsyncZip(#[from] async_zip::error::ZipError),
-    #[error("modrinth.index.json was not found within the modpack file")]
-    NotFound,
-}
-
-async fn read_index_data(buf: &mut Vec<u8>, zip: &mut ZipFileReader) -> Result<(), IndexReadError> {
-    let mut found = false;
-    for (i, file) in zip.file().entries().iter().enumerate() {
-        if file.filename().as_bytes() == "modrinth.index.json".as_bytes() {
-            found = true;
-            let mut entry = zip.reader_with_entry(i).await?;
-            entry.read_to_end_checked(buf).await?;
-            break;
+impl CliParameters {
+    fn target(&self) -> Target {
+        if self.server {
+            Target::Server
+        } else {
+            self.target
         }
     }
-    if !found {
-        Err(IndexReadError::NotFound)
-    } else {
-        Ok(())
+
+    /// Number of files to hash at once, which never exceeds --max-open-files.
+    fn verify_jobs(&self) -> usize {
+        self.verify_jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .unwrap_or(NonZeroUsize::MIN)
+            .min(self.max_open_files)
+            .get()
     }
 }
 
-fn sanitize_path_check(path: &Path, output_dir: &Path) {
-    let sanitized_path = canonicalize_recursively(path).unwrap();
-    if !sanitized_path.starts_with(output_dir) {
-        panic!(
-            "Path {} is outside of output dir ({})",
-            path.to_string_lossy(),
-            output_dir.to_string_lossy()
-        );
-    }
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Check a modpack for problems without downloading anything, for pack authors.
+    ///
+    /// Exits with an error if any problem is found.
+    Validate {
+        input_file: PathBuf,
+        /// Don't report download URLs on hosts outside the allowed list.
+        #[arg(long)]
+        skip_host_check: bool,
+    },
+    /// Print the SHA-1, SHA-256 and SHA-512 hashes of a modpack file, to publish along with it.
+    ///
+    /// Any of them can be checked when installing with --verify-pack.
+    Hash {
+        input_file: PathBuf,
+        /// Print a JSON object with all hashes.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the modrinth.index.json of a modpack exactly as it is stored, without checking it.
+    ///
+    /// Useful to see what a pack that fails to load actually contains.
+    DumpIndex {
+        input_file: PathBuf,
+        /// Write the index to FILE instead of printing it.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Reformat the index with indentation, if it is valid JSON. Object keys end up sorted.
+        #[arg(long)]
+        pretty: bool,
+    },
 }
 
-fn canonicalize_recursively(path: &Path) -> Option<PathBuf> {
-    for ancestor in path.ancestors() {
-        if ancestor.exists() {
-            return ancestor.canonicalize().ok();
-        }
-    }
-    None
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HostCheck {
+    /// Reject the modpack if any mirror of a file is on a disallowed host.
+    Any,
+    /// Only reject the modpack if all mirrors of a file are on disallowed hosts. The disallowed
+    /// mirrors are left out.
+    All,
 }
 
-fn sanitize_zip_filename(filename: &str) -> PathBuf {
-    filename
-        .replace('\\', "/")
-        .split('/')
-        .filter(|seg| !matches!(*seg, ".." | ""))
-        .collect()
-}
-
-async fn extract_folder(zip: &mut ZipFileReader, folder_name: &str, output_dir: &Path) {
-    for (i, entry) in zip.file().entries().iter().enumerate() {
-        let filename = entry.filename().as_str().unwrap();
-        if filename.starts_with(&format!("{folder_name}/")) {
-            println!("Extracting {filename}");
-            let zip_path =
-                sanitize_zip_filename(filename.strip_prefix(&format!("{folder_name}/")).unwrap());
-            let zip_path = output_dir.join(zip_path);
-            sanitize_path_check(&zip_path, output_dir);
-            if entry.dir().unwrap() {
-                if !zip_path.exists() {
-                    create_dir_all(&zip_path).await.unwrap()
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Target {
+    Client,
+    Server,
+    Both,
+}
+
+impl Target {
+    /// How much a file with the given environment is needed on this target.
+    fn requirement(self, env: &FileEnv) -> EnvRequirement {
+        match self {
+            Self::Client => env.client,
+            Self::Server => env.server,
+            Self::Both => match (env.client, env.server) {
+                (EnvRequirement::Required, _) | (_, EnvRequirement::Required) => {
+                    EnvRequirement::Required
                 }
-            } else {
-                let parent = zip_path.parent().unwrap();
-                if !parent.is_dir() {
-                    create_dir_all(parent).await.unwrap()
+                (EnvRequirement::Optional, _) | (_, EnvRequirement::Optional) => {
+                    EnvRequirement::Optional
                 }
-                let mut out_file = File::create(zip_path).await.unwrap();
-                let mut entry_reader = zip.reader_with_entry(i).await.unwrap().compat();
-                tokio::io::copy(&mut entry_reader, &mut out_file)
-                    .await
-                    .unwrap();
-            }
+                _ => EnvRequirement::Unsupported,
+            },
+        }
+    }
+
+    /// Override folders to extract, in order. Later folders overwrite files from earlier ones, so
+    /// the side specific folders win over the common `overrides`, as the modpack format intends.
+    fn override_folders(self) -> &'static [&'static str] {
+        match self {
+            Self::Client => &["overrides", "overrides-client"],
+            Self::Server => &["overrides", "overrides-server"],
+            Self::Both => &["overrides", "overrides-server", "overrides-client"],
         }
     }
 }
 
-async fn download_files(
-    index: ModrinthIndex,
-    output_dir: &Path,
-    ignore_hashes: bool,
-    jobs: usize,
-) -> Result<(), FileDownloadError> {
-    let mpb = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
-    let client = Client::new();
-    let files_stream = futures::stream::iter(index.files);
-    files_stream
-        .map::<Result<_, FileDownloadError>, _>(Ok)
-        .try_for_each_concurrent(jobs, |file| {
-            let client_clone = client.clone();
-            let mpb_clone = mpb.clone();
-            let path = output_dir.join(&file.path);
-            sanitize_path_check(&path, output_dir);
-            async move {
-                download_file(client_clone, &file.downloads, &path, mpb_clone).await?;
-                if !ignore_hashes {
-                    check_hashes(file.hashes, path).await;
-                };
-                Ok(())
-            }
-        })
-        .await
+/// Parses a size in bytes with an optional binary K, M, G or T suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = match size.char_indices().last() {
+        Some((i, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier = match suffix.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(format!("Unknown size suffix {suffix}")),
+            };
+            (&size[..i], multiplier)
+        }
+        _ => (size, 1),
+    };
+    number
+        .parse::<u64>()
+        .map_err(|why| why.to_string())?
+        .checked_mul(multiplier)
+        .ok_or_else(|| "Size is too large".to_string())
 }
 
 #[derive(Debug, Error)]
-enum FileTryDownloadError {
-    #[error("I/O error: {0}")]
-    IoError(#[from] std::io::Error),
-    #[error("Request error: {0}")]
-    RequestError(#[from] reqwest::Error),
-    #[error("Request to {url} failed. Status code: {status}; message: {message}")]
-    RequestFailed {
-        url: Url,
-        status: StatusCode,
-        message: String,
-    },
+enum IndexReadError {
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+    #[error("modrinth.index.json was not found within the modpack file")]
+    NotFound,
 }
 
-async fn try_download_file(
-    client: &Client,
-    url: &Url,
-    path: &Path,
-    bar: &ProgressBar,
-) -> Result<(), FileTryDownloadError> {
-    let res = client.get(url.clone()).send().await?;
-    let status = res.status();
-    if status.is_success() {
-        if let Some(total_size) = res.content_length() {
-            bar.set_length(total_size);
-        }
-
-        let mut out_file = File::create(path).await?;
-        let stream = res.bytes_stream();
-
-        let stream_reader = StreamReader::new(
-            stream.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
-        );
+fn find_index_entry(archive: &impl Archive) -> Result<ArchiveEntry, IndexReadError> {
+    archive
+        .entries()?
+        .into_iter()
+        .find(|entry| entry.name == "modrinth.index.json")
+        .ok_or(IndexReadError::NotFound)
+}
 
-        let mut bar_reader = bar.wrap_async_read(stream_reader);
+async fn read_index_data(buf: &mut Vec<u8>, archive: &impl Archive) -> Result<(), IndexReadError> {
+    let entry = find_index_entry(archive)?;
+    let size = entry.size;
+    let spinner = ProgressBar::with_draw_target(None, ProgressDrawTarget::stdout())
+        .with_style(ProgressStyle::default_spinner())
+        .with_message(format!("Reading index... ({size} bytes)"));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    archive.read_entry(entry.index, buf).await?;
+    spinner.finish_with_message(format!("Read index ({size} bytes)"));
+    Ok(())
+}
 
-        tokio::io::copy(&mut bar_reader, &mut out_file).await?;
+#[derive(Debug, Error)]
+#[error("Path {} is outside of output dir ({})", path.to_string_lossy(), output_dir.to_string_lossy())]
+struct UnsafePathError {
+    path: PathBuf,
+    output_dir: PathBuf,
+}
 
-        Ok(())
-    } else {
-        Err(FileTryDownloadError::RequestFailed {
-            url: url.clone(),
-            status,
-            message: res.text().await?,
-        })
+fn sanitize_path_check(path: &Path, output_dir: &Path) -> Result<(), UnsafePathError> {
+    match canonicalize_recursively(path) {
+        Some(sanitized_path) if sanitized_path.starts_with(output_dir) => Ok(()),
+        _ => Err(UnsafePathError {
+            path: path.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+        }),
     }
 }
 
-#[derive(Debug, Error)]
-enum FileDownloadError {
-    #[error("I/O error: {0}")]
-    IoError(#[from] std::io::Error),
-    #[error("All downloads have failed")]
-    AllDownloadsFailed,
+/// Canonicalizes the deepest existing ancestor of `path` and appends the rest of it, resolving
+/// `..` lexically. This gives the path a file would actually be written to, even through symlinks.
+///
+/// Returns `None` if the deepest existing ancestor can't be canonicalized, such as a dangling
+/// symlink, since it's unknown where writing through it would end up.
+fn canonicalize_recursively(path: &Path) -> Option<PathBuf> {
+    for ancestor in path.ancestors() {
+        // symlink_metadata also succeeds for dangling symlinks, unlike exists()
+        if ancestor.symlink_metadata().is_ok() {
+            let mut canonical = ancestor.canonicalize().ok()?;
+            for component in path.strip_prefix(ancestor).ok()?.components() {
+                match component {
+                    Component::Normal(part) => canonical.push(part),
+                    Component::ParentDir => {
+                        canonical.pop();
+                    }
+                    _ => {}
+                }
+            }
+            return Some(canonical);
+        }
+    }
+    None
 }
 
-async fn download_file(
-    client: Client,
-    urls: &[Url],
-    path: &Path,
-    progress_bars: MultiProgress,
-) -> Result<(), FileDownloadError> {
-    let pb = progress_bars.add(
-        ProgressBar::with_draw_target(None, ProgressDrawTarget::stdout())
-            .with_message(format!("Downloading {}", path.to_string_lossy()))
-            .with_style(
-                ProgressStyle::default_bar()
-                .template("{msg}\n{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").expect("Incorrect template provided")
-                .progress_chars("#> ")
-            ),
-    );
+/// Sanitizes a path from the index the same way as override paths. Absolute and empty paths have
+/// no sensible place in the output directory, so they are rejected.
+fn sanitize_file_path(path: &Path) -> Option<PathBuf> {
+    if path.has_root() || path.is_absolute() {
+        return None;
+    }
+    let sanitized = sanitize_zip_filename(&path.to_string_lossy());
+    (!sanitized.as_os_str().is_empty()).then_some(sanitized)
+}
 
-    // The directories will be created in case the parent directory doesn't exist or the parent is
-    // actually a file, which is an error condition and will be reported in the error.
-    if !path.parent().unwrap().is_dir() {
-        create_dir_all(path.parent().unwrap()).await?;
+/// How a path inside the output directory is shown in messages: relative to the output directory,
+/// unless `absolute` is set or the path is somewhere else.
+fn display_path(path: &Path, output_dir: &Path, absolute: bool) -> String {
+    match path.strip_prefix(output_dir) {
+        Ok(relative) if !absolute => relative.to_string_lossy().into_owned(),
+        _ => path.to_string_lossy().into_owned(),
     }
+}
 
-    let mut urls_iter = urls.iter();
+/// Expands a leading `~` and `$VAR` or `${VAR}` environment variables in `path`.
+///
+/// Variables whose value contains `..` components are rejected, so that the environment can't
+/// move the path outside of where the template points.
+fn expand_path(path: &Path) -> Result<PathBuf, CliError> {
+    // Paths that aren't valid UTF-8 can't contain anything to expand
+    let Some(template) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    let lookup = |name: &str| {
+        let value = std::env::var(name).map_err(|_| CliError::ExpandPath(name.to_string()))?;
+        if Path::new(&value)
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return Err(CliError::ExpandPathTraversal(name.to_string()));
+        }
+        Ok(value)
+    };
 
-    // This loop tries all urls until one of them succedes or it runs out of urls. The iterator is
-    // finite (fused) which guarantees that the loop will finish.
-    loop {
-        match urls_iter.next() {
-            // Try next url in the list
-            Some(url) => match try_download_file(&client, url, path, &pb).await {
-                // Downloads succeded, stop looping and return.
-                Ok(()) => {
-                    pb.finish_with_message(format!(
-                        "Downloaded {} from {}",
-                        path.to_string_lossy(),
-                        url
-                    ));
-                    break Ok(());
-                }
-                // An error occured. Report and go to the next url.
-                Err(why) => {
-                    eprintln!(
-                        "Failed to download file {} from {url}: {why}",
-                        path.to_string_lossy(),
-                    );
-                }
-            },
-            // No more urls to try.
-            None => {
-                pb.finish_with_message(format!("Failed to download {}", path.to_string_lossy()));
-                break Err(FileDownloadError::AllDownloadsFailed);
+    let mut expanded = String::new();
+    let mut rest = template;
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        let home = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+        expanded.push_str(&lookup(home)?);
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remaining) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some(split) => split,
+                None => return Err(CliError::ExpandPath(braced.to_string())),
             }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            after.split_at(end)
+        };
+        if name.is_empty() {
+            // A lone `$` is kept as is
+            expanded.push('$');
+        } else {
+            expanded.push_str(&lookup(name)?);
         }
+        rest = remaining;
     }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+/// Whether `path` is one of `selection`, either as a whole path or just as a file name.
+fn is_selected(path: &Path, selection: &[PathBuf]) -> bool {
+    selection
+        .iter()
+        .any(|selected| path == selected || path.file_name() == Some(selected.as_os_str()))
 }
 
-fn filter_file_list(files: &mut Vec<ModpackFile>, is_server: bool) {
+/// Drops the files that aren't needed on the chosen side. Optional files are taken from
+/// `selection` if given, otherwise they are asked about, unless `unattended` is set, in which case
+/// they are all kept.
+///
+/// Returns the optional files that were kept.
+fn filter_file_list(
+    files: &mut Vec<ModpackFile>,
+    target: Target,
+    unattended: bool,
+    selection: Option<&OptionalSelection>,
+) -> Result<Vec<PathBuf>, dialoguer::Error> {
+    let mut prompt_result = Ok(());
+    let mut included_optional = Vec::new();
+    let mut skipped_optional = 0;
     files.retain(|file| match &file.env {
         None => true,
-        Some(reqs) => {
-            let req = if is_server {
-                &reqs.server
-            } else {
-                &reqs.client
-            };
-            match req {
-                EnvRequirement::Required => true,
-                EnvRequirement::Unsupported => false,
-                EnvRequirement::Optional => !matches!(
-                    Confirm::new()
+        Some(env) => match target.requirement(env) {
+            EnvRequirement::Required => true,
+            EnvRequirement::Unsupported => false,
+            EnvRequirement::Optional => {
+                let include = match selection {
+                    Some(selection) => selection.include.contains(&file.path),
+                    // Once a prompt has failed, the rest of the optional files are left out
+                    None if prompt_result.is_err() => false,
+                    None if unattended => true,
+                    None => match Confirm::new()
                         .with_prompt(format!(
                             "Download optional {}?",
                             file.path.to_string_lossy()
@@ -289,12 +665,186 @@ fn filter_file_list(files: &mut Vec<ModpackFile>, is_server: bool) {
                         .default(true)
                         .wait_for_newline(false)
                         .interact_opt()
-                        .unwrap(),
-                    Some(false) | None
-                ),
+                    {
+                        Ok(answer) => !matches!(answer, Some(false) | None),
+                        Err(why) => {
+                            prompt_result = Err(why);
+                            false
+                        }
+                    },
+                };
+                // Decisions that weren't made by answering a prompt are logged
+                let reason = match selection {
+                    Some(_) => Some("selection file"),
+                    None if unattended => Some("unattended mode"),
+                    None => None,
+                };
+                if let Some(reason) = reason {
+                    log_println!(
+                        "{} optional {} ({reason})",
+                        if include { "Including" } else { "Skipping" },
+                        file.path.to_string_lossy()
+                    );
+                }
+                if include {
+                    included_optional.push(file.path.clone());
+                } else {
+                    skipped_optional += 1;
+                }
+                include
             }
+        },
+    });
+    if !included_optional.is_empty() || skipped_optional > 0 {
+        log_println!(
+            "Included {} optional files, skipped {skipped_optional}",
+            included_optional.len()
+        );
+    }
+    prompt_result.map(|()| included_optional)
+}
+
+/// Warns when the index uses a newer format version or fields this tool doesn't know. Such packs
+/// are still installed, as long as the fields that are known still parse.
+fn warn_about_format(index: &ModrinthIndex) {
+    if index.format_version > SUPPORTED_FORMAT_VERSION {
+        log_eprintln!(
+            "Warning: the modpack uses format version {}, but only version {SUPPORTED_FORMAT_VERSION} is supported. Installing it anyway",
+            index.format_version
+        );
+    }
+    let unknown_fields = index.unknown_fields();
+    if !unknown_fields.is_empty() {
+        log_eprintln!(
+            "Warning: ignoring unknown fields in the modpack index: {}",
+            unknown_fields.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+}
+
+/// Folders holding content that only the client uses.
+const CLIENT_ONLY_FOLDERS: &[&str] = &["resourcepacks", "shaderpacks"];
+
+/// Warns about files in a server install that only the client uses, going by the folder they are
+/// installed to, and drops them if `skip` is set.
+fn check_client_content(files: &mut Vec<ModpackFile>, skip: bool) {
+    files.retain(|file| {
+        let client_only = file.path.components().next().is_some_and(|folder| {
+            CLIENT_ONLY_FOLDERS.contains(&&*folder.as_os_str().to_string_lossy())
+        });
+        if !client_only {
+            return true;
         }
-    })
+        if skip {
+            log_println!(
+                "Skipping client-only {} for the server",
+                file.path.to_string_lossy()
+            );
+        } else {
+            log_eprintln!(
+                "Warning: {} is client-only content, but the pack installs it on the server. Pass --skip-client-content to leave it out",
+                file.path.to_string_lossy()
+            );
+        }
+        !skip
+    });
+}
+
+/// Folders that the launcher loads mods and other downloaded content from.
+const CONTENT_FOLDERS: &[&str] = &["mods", "resourcepacks", "shaderpacks"];
+
+/// Warns about files that are installed outside of [`CONTENT_FOLDERS`]. The format allows any
+/// path, but a jar in `config` is unusual enough that users should know about it.
+fn warn_about_unusual_paths(files: &[ModpackFile]) {
+    for file in files {
+        let in_content_folder = file.path.parent().is_some_and(|parent| {
+            parent.components().next().is_some_and(|folder| {
+                CONTENT_FOLDERS.contains(&&*folder.as_os_str().to_string_lossy())
+            })
+        });
+        if !in_content_folder {
+            log_eprintln!(
+                "Warning: {} is installed outside of the usual {} folders",
+                file.path.to_string_lossy(),
+                CONTENT_FOLDERS.join("/")
+            );
+        }
+    }
+}
+
+/// Removes the files larger than `max_size` from `files`, returning them.
+fn take_oversized(files: &mut Vec<ModpackFile>, max_size: u64) -> Vec<ModpackFile> {
+    let (oversized, fitting) = std::mem::take(files)
+        .into_iter()
+        .partition(|file| u64::from(file.file_size) > max_size);
+    *files = fitting;
+    for file in &oversized {
+        log_eprintln!(
+            "Warning: skipping {}, its {} bytes are more than --max-file-size",
+            file.path.to_string_lossy(),
+            file.file_size
+        );
+    }
+    oversized
+}
+
+/// Pairs of paths that differ only in case, which end up as the same file on case-insensitive
+/// filesystems like the defaults of Windows and macOS. Identical paths are not collisions, later
+/// override folders are meant to replace files.
+fn find_case_collisions<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Vec<(&'a Path, &'a Path)> {
+    let mut seen: HashMap<String, &Path> = HashMap::new();
+    let mut collisions = Vec::new();
+    for path in paths {
+        let folded = path.to_string_lossy().to_lowercase();
+        match seen.get(&folded) {
+            Some(&first) if first != path => collisions.push((first, path)),
+            Some(_) => {}
+            None => {
+                seen.insert(folded, path);
+            }
+        }
+    }
+    collisions
+}
+
+/// Adds back the files from `unfiltered` matching `force_include` that filtering dropped, then
+/// removes every file matching `force_exclude`, which wins over `force_include`.
+fn apply_forced_files(
+    files: &mut Vec<ModpackFile>,
+    unfiltered: Vec<ModpackFile>,
+    force_include: &[String],
+    force_exclude: &[String],
+) {
+    let matches_any = |patterns: &[String], file: &ModpackFile| {
+        let path = file
+            .path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        patterns.iter().any(|pattern| glob_match(pattern, &path))
+    };
+    for file in unfiltered {
+        if matches_any(force_include, &file) && !files.iter().any(|kept| kept.path == file.path) {
+            log_println!(
+                "Including {} because of --force-include",
+                file.path.to_string_lossy()
+            );
+            files.push(file);
+        }
+    }
+    files.retain(|file| {
+        let excluded = matches_any(force_exclude, file);
+        if excluded {
+            log_println!(
+                "Excluding {} because of --force-exclude",
+                file.path.to_string_lossy()
+            );
+        }
+        !excluded
+    });
 }
 
 #[derive(Debug, Error)]
@@ -303,78 +853,918 @@ enum IndexGetError {
     ReadError(#[from] IndexReadError),
     #[error("Failed to deserialize index file: {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("Invalid index file: {}: {}", .0.path, .0.problem)]
+    Invalid(IndexProblem),
 }
 
-async fn get_index_data(zip_file: &mut ZipFileReader) -> Result<ModrinthIndex, IndexGetError> {
+async fn get_index_data(archive: &impl Archive) -> Result<ModrinthIndex, IndexGetError> {
     let mut index_data: Vec<u8> = Vec::new();
-    read_index_data(&mut index_data, zip_file).await?;
+    read_index_data(&mut index_data, archive).await?;
 
-    serde_json::from_slice(&index_data).map_err(Into::into)
+    serde_json::from_slice(&index_data).map_err(|why| {
+        // Serde only knows the line and column, point at the exact field instead if possible
+        serde_json::from_slice(&index_data)
+            .ok()
+            .and_then(|index| find_index_problem(&index))
+            .map_or(IndexGetError::SerdeError(why), IndexGetError::Invalid)
+    })
 }
 
-#[tokio::main]
-async fn main() {
-    let parameters = CliParameters::parse();
+#[derive(Debug, Error)]
+enum CliError {
+    #[error("Modpack verification failed: {0}")]
+    PackVerify(#[from] PackVerifyError),
+    #[error("Failed to open modpack {}: {source}", path.to_string_lossy())]
+    OpenPack { path: PathBuf, source: ArchiveError },
+    #[error("Modpack {} uses {method} compression, which is not supported", path.to_string_lossy())]
+    UnsupportedCompression { path: PathBuf, method: String },
+    #[error("Failed to hash modpack {}: {source}", path.to_string_lossy())]
+    HashPack {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to read modpack index: {0}")]
+    Index(#[from] IndexGetError),
+    #[error("IP addresses are not allowed in download URLs: {0}")]
+    IpAddressUrl(Url),
+    #[error("Downloading from {0} is not allowed. See https://docs.modrinth.com/modpacks/format#downloads")]
+    DisallowedHost(String),
+    #[error("Failed to create output directory {}: {source}", path.to_string_lossy())]
+    CreateOutputDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Environment variable {0} in the output path is not set")]
+    ExpandPath(String),
+    #[error("Environment variable {0} in the output path contains `..`")]
+    ExpandPathTraversal(String),
+    #[error("Output path {} exists and is a file, not a directory", .0.to_string_lossy())]
+    OutputIsFile(PathBuf),
+    #[error("Failed to create a temporary output directory: {0}")]
+    TempDir(std::io::Error),
+    #[error("Failed to resolve output directory {}: {source}", path.to_string_lossy())]
+    ResolveOutputDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to use selection file {}: {source}", path.to_string_lossy())]
+    Selection {
+        path: PathBuf,
+        source: SelectionError,
+    },
+    #[error("Failed to write aria2c input file {}: {source}", path.to_string_lossy())]
+    WriteAria2 {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Found {0} paths that differ only in case. Pass --case-sensitive-check false to install anyway")]
+    CaseCollisions(usize),
+    #[error("Failed to write install summary {}: {source}", path.to_string_lossy())]
+    WriteSummary {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to check files against Modrinth: {0}")]
+    Upstream(#[from] UpstreamError),
+    #[error("{0} files don't match any file published on Modrinth")]
+    Unpublished(usize),
+    #[error("Failed to write the index: {0}")]
+    WriteIndex(std::io::Error),
+    #[error("Invalid file path in the modpack index: {0:?}")]
+    InvalidFilePath(PathBuf),
+    #[error("Failed to read CA certificate {}: {source}", path.to_string_lossy())]
+    ReadCaCert {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid CA certificate {}: {source}", path.to_string_lossy())]
+    InvalidCaCert {
+        path: PathBuf,
+        source: reqwest::Error,
+    },
+    #[error("Failed to set up the HTTP client: {0}")]
+    HttpClient(reqwest::Error),
+    #[error("Failed to run the post-install hook: {0}")]
+    PostHookSpawn(std::io::Error),
+    #[error("The post-install hook exited with {0}")]
+    PostHookFailed(std::process::ExitStatus),
+    #[error("Timed out after {0} seconds")]
+    Timeout(u64),
+    #[error("Failed to prompt: {0}")]
+    Prompt(#[from] dialoguer::Error),
+    #[error("Validation found {0} problems")]
+    ValidationFailed(usize),
+    #[error("Verification failed, some files are missing or corrupted")]
+    VerificationFailed,
+    #[error("Download failed: {0}")]
+    Download(#[from] FileDownloadError),
+    #[error("Failed to extract overrides: {0}")]
+    Extract(#[from] ExtractError),
+    #[error(transparent)]
+    UnsafePath(#[from] UnsafePathError),
+    #[error("The modpack exceeds the limits set by --max-files and --max-total-size")]
+    LimitsExceeded,
+}
+
+/// Guards against indexes listing an absurd amount of files or data. Returns whether to proceed.
+fn check_limits(
+    index: &ModrinthIndex,
+    max_files: usize,
+    max_total_size: u64,
+    unattended: bool,
+) -> Result<bool, dialoguer::Error> {
+    let total_size: u64 = index
+        .files
+        .iter()
+        .map(|file| u64::from(file.file_size))
+        .sum();
+    let mut exceeded = false;
+    if index.files.len() > max_files {
+        log_eprintln!(
+            "Warning: the modpack lists {} files, more than the limit of {max_files}",
+            index.files.len()
+        );
+        exceeded = true;
+    }
+    if total_size > max_total_size {
+        log_eprintln!(
+            "Warning: the modpack lists {total_size} bytes of files, more than the limit of {max_total_size}"
+        );
+        exceeded = true;
+    }
+    if !exceeded {
+        return Ok(true);
+    }
+    if unattended {
+        return Ok(false);
+    }
+    Ok(matches!(
+        Confirm::new()
+            .with_prompt("Proceed anyway?")
+            .default(false)
+            .wait_for_newline(true)
+            .interact_opt()?,
+        Some(true)
+    ))
+}
+
+/// Whether downloading from `url` is allowed by the modpack format.
+fn check_host(url: &Url) -> Result<(), CliError> {
+    let domain = url
+        .domain()
+        .ok_or_else(|| CliError::IpAddressUrl(url.clone()))?;
+    if ALLOWED_HOSTS.contains(&domain) {
+        Ok(())
+    } else {
+        Err(CliError::DisallowedHost(domain.to_string()))
+    }
+}
+
+/// Lists the disallowed download hosts of `files` and asks whether to download from them anyway.
+/// Returns `false` right away if there are none.
+fn confirm_disallowed_hosts(files: &[ModpackFile]) -> Result<bool, dialoguer::Error> {
+    let mut hosts: BTreeMap<String, usize> = BTreeMap::new();
+    for url in files.iter().flat_map(|file| &file.downloads) {
+        if check_host(url).is_err() {
+            let host = url.host_str().unwrap_or(url.as_str()).to_string();
+            *hosts.entry(host).or_default() += 1;
+        }
+    }
+    if hosts.is_empty() {
+        return Ok(false);
+    }
+    log_eprintln!("The modpack downloads from hosts that Modrinth doesn't allow:");
+    for (host, count) in &hosts {
+        log_eprintln!("{host}: {count} urls");
+    }
+    Ok(Confirm::new()
+        .with_prompt("Download from these hosts anyway?")
+        .default(false)
+        .wait_for_newline(true)
+        .interact_opt()?
+        .unwrap_or(false))
+}
+
+/// Creates `output_dir` with all of its parents and returns its canonical path.
+///
+/// The directory may be removed again before it's resolved, in which case creating it is retried
+/// once before giving up.
+async fn prepare_output_dir(output_dir: &Path) -> Result<PathBuf, CliError> {
+    let mut retried = false;
+    loop {
+        create_dir_all(output_dir)
+            .await
+            .map_err(|source| CliError::CreateOutputDir {
+                path: output_dir.to_path_buf(),
+                source,
+            })?;
+        match output_dir.canonicalize() {
+            Ok(path) => break Ok(path),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound && !retried => retried = true,
+            Err(source) => {
+                break Err(CliError::ResolveOutputDir {
+                    path: output_dir.to_path_buf(),
+                    source,
+                })
+            }
+        }
+    }
+}
+
+async fn open_pack(path: &Path) -> Result<PackArchive, CliError> {
+    PackArchive::open(path)
+        .await
+        .map_err(|source| match source {
+            // Every entry's compression is checked up front, so this is the only place it can fail
+            ArchiveError::Zip(ZipError::CompressionNotSupported(method)) => {
+                CliError::UnsupportedCompression {
+                    path: path.to_path_buf(),
+                    method: compression_name(method),
+                }
+            }
+            source => CliError::OpenPack {
+                path: path.to_path_buf(),
+                source,
+            },
+        })
+}
+
+/// Name of a zip compression method, from the numbers in the zip specification. Only methods that
+/// async_zip can't decompress are named.
+fn compression_name(method: u16) -> String {
+    match method {
+        9 => "Deflate64".to_string(),
+        14 => "LZMA".to_string(),
+        95 => "XZ".to_string(),
+        method => format!("method {method}"),
+    }
+}
+
+async fn run_cli(parameters: CliParameters) -> Result<(), CliError> {
+    let started = Instant::now();
+    match &parameters.command {
+        Some(Command::Validate {
+            input_file,
+            skip_host_check,
+        }) => {
+            let archive = open_pack(input_file).await?;
+            let problems = validate_pack(&archive, *skip_host_check).await;
+            return if problems == 0 {
+                log_println!("PASS");
+                Ok(())
+            } else {
+                log_println!("FAIL: {problems} problems");
+                Err(CliError::ValidationFailed(problems))
+            };
+        }
+        Some(Command::Hash { input_file, json }) => {
+            let hashes = hash_pack(input_file)
+                .await
+                .map_err(|source| CliError::HashPack {
+                    path: input_file.clone(),
+                    source,
+                })?;
+            if *json {
+                log_println!("{}", serde_json::json!(hashes));
+            } else {
+                log_println!("sha1:   {}", hashes.sha1);
+                log_println!("sha256: {}", hashes.sha256);
+                log_println!("sha512: {}", hashes.sha512);
+            }
+            return Ok(());
+        }
+        Some(Command::DumpIndex {
+            input_file,
+            output,
+            pretty,
+        }) => {
+            let archive = open_pack(input_file).await?;
+            let entry = find_index_entry(&archive).map_err(IndexGetError::from)?;
+            let mut index_data = Vec::new();
+            archive
+                .read_entry(entry.index, &mut index_data)
+                .await
+                .map_err(|why| IndexGetError::from(IndexReadError::from(why)))?;
+            if *pretty {
+                match serde_json::from_slice::<serde_json::Value>(&index_data) {
+                    Ok(index) => {
+                        index_data = serde_json::to_vec_pretty(&index)
+                            .expect("A JSON value can always be serialized");
+                        index_data.push(b'\n');
+                    }
+                    Err(why) => log_eprintln!("Warning: not reformatting the index: {why}"),
+                }
+            }
+            match output {
+                Some(path) => std::fs::write(path, &index_data),
+                None => std::io::stdout().write_all(&index_data),
+            }
+            .map_err(CliError::WriteIndex)?;
+            return Ok(());
+        }
+        None => {}
+    }
+    // Clap requires both when there's no subcommand
+    let Some(input_file) = &parameters.input_file else {
+        unreachable!("Missing input file");
+    };
+    let output_dir = &match &parameters.output_dir {
+        Some(output_dir) => expand_path(output_dir)?,
+        None => tempfile::Builder::new()
+            .prefix("mrpack-")
+            .tempdir()
+            .map_err(CliError::TempDir)?
+            .keep(),
+    };
+    // Otherwise creating the directory fails with a confusing error, after the pack was read
+    if let Some(file) = output_dir
+        .ancestors()
+        .find(|path| path.exists())
+        .filter(|path| !path.is_dir())
+    {
+        return Err(CliError::OutputIsFile(file.to_path_buf()));
+    }
+
+    if let Some(checksum) = &parameters.verify_pack {
+        verify_pack(input_file, checksum).await?;
+        log_println!("Modpack checksum verified");
+    }
+
+    let archive = open_pack(input_file).await?;
+
+    let mut modrinth_index_data = get_index_data(&archive).await?;
+    warn_about_format(&modrinth_index_data);
+    for file in &mut modrinth_index_data.files {
+        file.path = sanitize_file_path(&file.path)
+            .ok_or_else(|| CliError::InvalidFilePath(file.path.clone()))?;
+    }
+    let unattended = parameters.unattended || !std::io::stdin().is_terminal();
 
-    let mut zip_file = ZipFileReader::new(parameters.input_file).await.unwrap();
-
-    let mut modrinth_index_data = get_index_data(&mut zip_file).await.unwrap();
-    if !parameters.skip_host_check {
-        for file in modrinth_index_data.files.iter() {
-            for url in file.downloads.iter() {
-                if !ALLOWED_HOSTS.contains(
-                    &url.domain()
-                        .expect("IP addresses are not allowed in download URLs"),
-                ) {
-                    panic!("Downloading from {} is not allowed. See https://docs.modrinth.com/modpacks/format#downloads", url.domain().unwrap());
+    // Nothing is downloaded, so neither the hosts nor the limits of the files matter
+    if parameters.only_overrides {
+        modrinth_index_data.print_info();
+        log_println!(
+            "Only extracting overrides, skipping all {} files to download",
+            modrinth_index_data.files.len()
+        );
+        modrinth_index_data.files.clear();
+    }
+
+    let skip_host_check = parameters.skip_host_check
+        || (parameters.confirm_hosts
+            && !unattended
+            && confirm_disallowed_hosts(&modrinth_index_data.files)?);
+    if !skip_host_check {
+        for file in modrinth_index_data.files.iter_mut() {
+            match parameters.host_check {
+                HostCheck::Any => {
+                    for url in file.downloads.iter() {
+                        check_host(url)?;
+                    }
+                }
+                HostCheck::All => {
+                    let mut last_error = None;
+                    file.downloads.retain(|url| match check_host(url) {
+                        Ok(()) => true,
+                        Err(why) => {
+                            log_eprintln!(
+                                "Warning: ignoring mirror of {}: {why}",
+                                file.path.to_string_lossy()
+                            );
+                            last_error = Some(why);
+                            false
+                        }
+                    });
+                    if file.downloads.is_empty() {
+                        if let Some(why) = last_error {
+                            return Err(why);
+                        }
+                    }
                 }
             }
         }
     }
 
-    let target_path = parameters.output_dir.canonicalize().unwrap();
+    let output_dir = if parameters.name_subfolder {
+        output_dir.join(modrinth_index_data.folder_name())
+    } else {
+        output_dir.clone()
+    };
+
+    if parameters.print_urls {
+        print_urls(
+            &modrinth_index_data.files,
+            parameters.target(),
+            parameters.json,
+        );
+        return Ok(());
+    }
+
+    if let Some(path) = &parameters.emit_aria2 {
+        write_aria2_input(
+            path,
+            &modrinth_index_data.files,
+            parameters.target(),
+            &output_dir,
+        )
+        .map_err(|source| CliError::WriteAria2 {
+            path: path.clone(),
+            source,
+        })?;
+        log_println!(
+            "Wrote aria2c input file {}, download with `aria2c -i {}`",
+            path.to_string_lossy(),
+            path.to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    if !check_limits(
+        &modrinth_index_data,
+        parameters.max_files,
+        parameters.max_total_size,
+        unattended,
+    )? {
+        return Err(CliError::LimitsExceeded);
+    }
+
+    // Every path check from here on is done against this canonical path
+    let target_path = prepare_output_dir(&output_dir).await?;
+
+    if !parameters.only_overrides {
+        modrinth_index_data.print_info();
+    }
+
+    if !parameters.only.is_empty() {
+        modrinth_index_data
+            .files
+            .retain(|file| is_selected(&file.path, &parameters.only));
+        log_println!(
+            "Only downloading files matching --only: {}",
+            modrinth_index_data.files.len()
+        );
+    }
+
+    let target = parameters.target();
+    match target {
+        Target::Client => (),
+        Target::Server => log_println!("Downloading as a server version is enabled"),
+        Target::Both => log_println!("Downloading for both client and server is enabled"),
+    }
+
+    let selection = match &parameters.selection {
+        Some(path) => {
+            Some(
+                OptionalSelection::load(path).map_err(|source| CliError::Selection {
+                    path: path.clone(),
+                    source,
+                })?,
+            )
+        }
+        None => None,
+    };
+
+    // Only needed to bring back files that filtering drops
+    let unfiltered_files = if parameters.force_include.is_empty() {
+        Vec::new()
+    } else {
+        modrinth_index_data.files.clone()
+    };
+
+    let included_optional = filter_file_list(
+        &mut modrinth_index_data.files,
+        target,
+        unattended,
+        selection.as_ref(),
+    )?;
+
+    if target == Target::Server {
+        check_client_content(
+            &mut modrinth_index_data.files,
+            parameters.skip_client_content,
+        );
+    }
+
+    apply_forced_files(
+        &mut modrinth_index_data.files,
+        unfiltered_files,
+        &parameters.force_include,
+        &parameters.force_exclude,
+    );
 
-    modrinth_index_data.print_info();
+    warn_about_unusual_paths(&modrinth_index_data.files);
 
-    if parameters.server {
-        println!("Downloading as a server version is enabled");
+    if let Some(path) = &parameters.save_selection {
+        OptionalSelection {
+            include: included_optional,
+        }
+        .save(path)
+        .map_err(|source| CliError::Selection {
+            path: path.clone(),
+            source,
+        })?;
     }
 
-    filter_file_list(&mut modrinth_index_data.files, parameters.server);
+    let oversized = match parameters.max_file_size {
+        Some(max_size) => take_oversized(&mut modrinth_index_data.files, max_size),
+        None => Vec::new(),
+    };
 
-    println!(
+    log_println!(
         "Total amount of files to download after filtering: {}",
         modrinth_index_data.files.len()
     );
 
-    match Confirm::new()
-        .with_prompt("Proceed to downloading?")
-        .default(true)
-        .wait_for_newline(true)
-        .interact_opt()
-        .unwrap()
-    {
-        Some(false) | None => return,
-        _ => (),
+    let extract_options = ExtractOptions {
+        symlinks: parameters.symlinks,
+        absolute_paths: parameters.absolute_paths,
+        strip_components: parameters.strip_components,
+        keep_junk: parameters.keep_junk,
+        verbose: parameters.verbose,
+        keep_going: parameters.keep_going_on_extract_fail,
+        only_changed: parameters.only_changed_overrides,
+        jobs: parameters.extract_jobs.min(parameters.max_open_files).get(),
+    };
+
+    let mut installed_paths: Vec<PathBuf> = modrinth_index_data
+        .files
+        .iter()
+        .map(|file| file.path.clone())
+        .collect();
+    for folder in target.override_folders() {
+        installed_paths
+            .extend(override_paths(&archive, folder, extract_options).map_err(ExtractError::from)?);
+    }
+    let collisions = find_case_collisions(installed_paths.iter().map(PathBuf::as_path));
+    for (first, second) in &collisions {
+        log_eprintln!(
+            "Warning: {} and {} differ only in case and overwrite each other on case-insensitive filesystems",
+            first.to_string_lossy(),
+            second.to_string_lossy()
+        );
+    }
+    if parameters.case_sensitive_check && !collisions.is_empty() {
+        return Err(CliError::CaseCollisions(collisions.len()));
+    }
+
+    // Built once, so that its warnings are only printed once
+    let client = build_client(&parameters)?;
+    if parameters.verify_upstream {
+        log_println!("Checking the files against Modrinth");
+        let report = verify_upstream(
+            &modrinth_index_data.files,
+            &client,
+            &parameters.modrinth_api,
+            parameters.jobs.get(),
+        )
+        .await?;
+        report.print();
+        if !report.is_ok() {
+            return Err(CliError::Unpublished(report.unpublished.len()));
+        }
+    }
+
+    if parameters.verify {
+        let report = verify_files(
+            modrinth_index_data.files,
+            &target_path,
+            parameters.verify_jobs(),
+            parameters.absolute_paths,
+        )
+        .await?;
+        report.print(&target_path, parameters.absolute_paths);
+        return if report.is_ok() {
+            Ok(())
+        } else {
+            Err(CliError::VerificationFailed)
+        };
     }
 
-    println!("Downloading files");
-    if let Err(why) = download_files(
-        modrinth_index_data,
-        &target_path,
-        parameters.ignore_hashes,
+    if !unattended {
+        match Confirm::new()
+            .with_prompt("Proceed to downloading?")
+            .default(true)
+            .wait_for_newline(true)
+            .interact_opt()?
+        {
+            Some(false) | None => return Ok(()),
+            _ => (),
+        }
+    }
+
+    let limiter = AdaptiveLimiter::new(
         parameters.jobs.get(),
-    )
-    .await
-    {
-        panic!("Download failed: {why}");
+        parameters.min_jobs.get(),
+        parameters.max_jobs.unwrap_or(parameters.jobs).get(),
+    );
+
+    let retry_budget = RetryBudget::new(
+        parameters.max_total_retries,
+        parameters.deadline.map(Duration::from_secs),
+    );
+
+    let open_files = Semaphore::new(parameters.max_open_files.get());
+
+    let download_options = DownloadOptions {
+        ignore_hashes: parameters.ignore_hashes,
+        max_retries: parameters.max_retries,
+        verbose: parameters.verbose,
+        preserve_mtime: parameters.preserve_mtime,
+        force: parameters.force,
+        keep_going_on_hash_fail: parameters.keep_going_on_hash_fail,
+        absolute_paths: parameters.absolute_paths,
+        defer_hash_check: parameters.defer_hash_check,
+        trust_hashes: parameters.trust_hashes,
+        skip_check: parameters.skip_check,
+        per_host_jobs: parameters.per_host_jobs.map(NonZeroUsize::get),
+        file_timeout: parameters.file_timeout.map(Duration::from_secs),
+    };
+    let deferred_files = parameters
+        .defer_hash_check
+        .then(|| modrinth_index_data.files.clone());
+
+    if modrinth_index_data.files.is_empty() {
+        log_println!("No files to download, only extracting overrides");
+    } else {
+        log_println!("Downloading files");
+    }
+    let http = HttpClient {
+        client,
+        github_token: parameters.github_token.clone(),
+        mirror_auth: parameters.mirror_auth.clone(),
+    };
+    warn_about_plain_http_mirror_auth(&http, &modrinth_index_data.files);
+
+    let mut report = DownloadReport::default();
+    let mut extracted = HashMap::new();
+    let result: Result<(), CliError> = async {
+        let download_result;
+        (report, download_result) = download_files(
+            std::mem::take(&mut modrinth_index_data.files),
+            &target_path,
+            &http,
+            &limiter,
+            &retry_budget,
+            &open_files,
+            download_options,
+        )
+        .await;
+        download_result?;
+        if let Some(files) = deferred_files {
+            log_println!("Checking hashes of the downloaded files");
+            verify_deferred(
+                files,
+                &target_path,
+                parameters.verify_jobs(),
+                download_options,
+                &mut report,
+            )
+            .await?;
+        }
+        if parameters.verbose {
+            report.print_mirror_stats();
+        }
+        for path in &report.hash_failures {
+            log_eprintln!(
+                "File {} failed the hash check and was not installed",
+                display_path(path, &target_path, parameters.absolute_paths)
+            );
+        }
+
+        log_println!("Extracting additional files (overrides)");
+        let mut failed_entries = 0;
+        for folder in target.override_folders() {
+            failed_entries += extract_folder(
+                &archive,
+                folder,
+                &target_path,
+                &open_files,
+                &mut extracted,
+                extract_options,
+            )
+            .await?;
+        }
+        if failed_entries > 0 {
+            log_eprintln!("{failed_entries} override files failed to extract and were skipped");
+        }
+
+        if let Some(command) = &parameters.post_hook {
+            run_post_hook(
+                command,
+                &modrinth_index_data.name,
+                &modrinth_index_data.version_id,
+                &target_path,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+    .await;
+
+    if result.is_ok() && !oversized.is_empty() {
+        log_eprintln!(
+            "These files were skipped for their size and have to be downloaded manually:"
+        );
+        for file in &oversized {
+            let url = file.downloads.first().map_or("", Url::as_str);
+            log_eprintln!("{}: {url}", file.path.to_string_lossy());
+        }
+    }
+
+    // Written for failed installs too, those are the ones worth attaching to a bug report
+    if let Some(path) = &parameters.summary_json {
+        InstallSummary::new(
+            &modrinth_index_data,
+            &target_path,
+            &report,
+            &oversized,
+            &extracted,
+            started.elapsed(),
+            result.as_ref().err().map(ToString::to_string),
+        )
+        .save(path)
+        .map_err(|source| CliError::WriteSummary {
+            path: path.clone(),
+            source,
+        })?;
+    }
+    result?;
+
+    if parameters.temp_output {
+        log_println!("Installed into {}", target_path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Prints the download URLs of every file `target` may need, one per line, or as a JSON object
+/// mapping each path to its URLs.
+fn print_urls(files: &[ModpackFile], target: Target, json: bool) {
+    let files = supported_files(files, target);
+    if json {
+        let urls: serde_json::Map<String, serde_json::Value> = files
+            .map(|file| {
+                (
+                    file.path.to_string_lossy().into_owned(),
+                    file.downloads.iter().map(Url::as_str).collect(),
+                )
+            })
+            .collect();
+        log_println!("{}", serde_json::Value::Object(urls));
+    } else {
+        for url in files.flat_map(|file| &file.downloads) {
+            log_println!("{url}");
+        }
+    }
+}
+
+/// Writes an aria2c input file downloading every file `target` may need into `output_dir`, with
+/// the mirrors of a file on one line and its hash checked by aria2c.
+fn write_aria2_input(
+    path: &Path,
+    files: &[ModpackFile],
+    target: Target,
+    output_dir: &Path,
+) -> std::io::Result<()> {
+    // aria2c resolves relative directories against where it is run, not where the file is
+    let output_dir = std::path::absolute(output_dir)?;
+    let mut input = String::new();
+    for file in supported_files(files, target) {
+        let urls: Vec<&str> = file.downloads.iter().map(Url::as_str).collect();
+        input.push_str(&urls.join("\t"));
+        input.push('\n');
+        input.push_str(&format!("  dir={}\n", output_dir.to_string_lossy()));
+        input.push_str(&format!("  out={}\n", file.path.to_string_lossy()));
+        input.push_str(&format!(
+            "  checksum=sha-512={}\n",
+            hex::encode(file.hashes.sha512)
+        ));
+    }
+    std::fs::write(path, input)
+}
+
+/// Files of the index that `target` may need, including optional ones.
+fn supported_files(files: &[ModpackFile], target: Target) -> impl Iterator<Item = &ModpackFile> {
+    files.iter().filter(move |file| {
+        file.env
+            .as_ref()
+            .is_none_or(|env| target.requirement(env) != EnvRequirement::Unsupported)
+    })
+}
+
+/// Warns once for each `--mirror-auth` host that files are downloaded from over plain http, where
+/// the credentials aren't sent.
+fn warn_about_plain_http_mirror_auth(http: &HttpClient, files: &[ModpackFile]) {
+    for auth in &http.mirror_auth {
+        let plain_http = files
+            .iter()
+            .flat_map(|file| &file.downloads)
+            .any(|url| url.scheme() == "http" && auth.matches(url));
+        if plain_http {
+            log_eprintln!(
+                "Warning: not sending the --mirror-auth credentials for {} over plain http",
+                auth.host
+            );
+        }
+    }
+}
+
+/// The HTTP client for all downloads, trusting the extra root certificate if one is given.
+fn build_client(parameters: &CliParameters) -> Result<reqwest::Client, CliError> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(path) = &parameters.ca_cert {
+        let data = std::fs::read(path).map_err(|source| CliError::ReadCaCert {
+            path: path.clone(),
+            source,
+        })?;
+        // PEM files are text, anything else is taken to be DER
+        let certificate = if data.starts_with(b"-----") {
+            reqwest::Certificate::from_pem(&data)
+        } else {
+            reqwest::Certificate::from_der(&data)
+        }
+        .map_err(|source| CliError::InvalidCaCert {
+            path: path.clone(),
+            source,
+        })?;
+        builder = builder.add_root_certificate(certificate);
     }
+    if parameters.danger_accept_invalid_certs {
+        log_eprintln!(
+            "Warning: TLS certificates are not checked, anyone on the network can tamper with downloads"
+        );
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder.build().map_err(CliError::HttpClient)
+}
 
-    println!("Extracting additional files (overrides)");
-    extract_folder(&mut zip_file, "overrides", &target_path).await;
-    if parameters.server {
-        extract_folder(&mut zip_file, "overrides-server", &target_path).await;
+/// Runs `command` through the system shell inside `output_dir`, failing if it doesn't exit
+/// successfully.
+async fn run_post_hook(
+    command: &str,
+    pack_name: &str,
+    pack_version: &str,
+    output_dir: &Path,
+) -> Result<(), CliError> {
+    log_println!("Running post-install hook: {command}");
+    let mut shell = if cfg!(windows) {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
     } else {
-        extract_folder(&mut zip_file, "overrides-client", &target_path).await;
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .current_dir(output_dir)
+        .env("MRPACK_NAME", pack_name)
+        .env("MRPACK_VERSION", pack_version)
+        .env("MRPACK_OUTPUT_DIR", output_dir)
+        // --global-timeout drops this future, which would leave a hung hook running otherwise
+        .kill_on_drop(true)
+        .status()
+        .await
+        .map_err(CliError::PostHookSpawn)?;
+    if status.success() {
+        log_println!("Post-install hook finished successfully");
+        Ok(())
+    } else {
+        Err(CliError::PostHookFailed(status))
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let parameters = CliParameters::parse();
+
+    if let Some(path) = &parameters.log_file {
+        if let Err(why) = log_file::open(path) {
+            eprintln!(
+                "Error: Failed to create log file {}: {why}",
+                path.to_string_lossy()
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+
+    // Dropping the run on timeout cancels everything in flight and cleans up partial files
+    let result = match parameters.global_timeout {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), run_cli(parameters))
+            .await
+            .unwrap_or(Err(CliError::Timeout(secs))),
+        None => run_cli(parameters).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(why) => {
+            log_eprintln!("Error: {why}");
+            match why {
+                CliError::Timeout(_) => ExitCode::from(124),
+                _ => ExitCode::FAILURE,
+            }
+        }
     }
 }
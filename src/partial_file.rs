@@ -0,0 +1,33 @@
+use std::path::{Path, PathBuf};
+
+/// Deletes a file that is being written when dropped, unless it was marked as complete with
+/// [`PartialFile::keep`].
+///
+/// This cleans up after failed writes as well as after cancellation, such as when the whole run
+/// times out, since dropping the future drops the guard too.
+#[derive(Debug)]
+pub(crate) struct PartialFile {
+    path: Option<PathBuf>,
+}
+
+impl PartialFile {
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    /// The file is complete and must stay.
+    pub(crate) fn keep(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            // The file may have never been created or already been removed
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
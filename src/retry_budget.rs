@@ -0,0 +1,40 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Limit on retries shared by all downloads of a modpack, so that a dead mirror can't make the
+/// whole run drag on for hours.
+#[derive(Debug)]
+pub(crate) struct RetryBudget {
+    remaining: Option<AtomicUsize>,
+    deadline: Option<Instant>,
+}
+
+impl RetryBudget {
+    /// Neither limit is applied when set to `None`. The deadline is counted from now.
+    pub(crate) fn new(max_retries: Option<usize>, deadline: Option<Duration>) -> Self {
+        Self {
+            remaining: max_retries.map(AtomicUsize::new),
+            deadline: deadline.map(|duration| Instant::now() + duration),
+        }
+    }
+
+    /// Takes one retry out of the budget. Returns `false` if there's none left.
+    pub(crate) fn try_take(&self) -> bool {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return false;
+        }
+        match &self.remaining {
+            Some(remaining) => remaining
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok(),
+            None => true,
+        }
+    }
+}
@@ -1,10 +1,22 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use semver::Version;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum_macros::AsRefStr;
+use thiserror::Error;
 use url::Url;
 
+use crate::log_file::log_println;
+
+/// The newest version of the modpack format this tool knows about.
+pub const SUPPORTED_FORMAT_VERSION: u32 = 1;
+
+// Not every field of the format is used, but they are kept to mirror the specification.
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ModrinthIndex {
@@ -14,22 +26,119 @@ pub struct ModrinthIndex {
     pub name: String,
     pub summary: Option<String>,
     pub files: Vec<ModpackFile>,
-    pub dependencies: HashMap<ModpackDependencyId, Version>,
+    pub dependencies: HashMap<ModpackDependencyId, DependencyVersion>,
+    /// Fields that aren't part of the known format, like ones added by a newer version of it.
+    #[serde(flatten)]
+    pub other_fields: HashMap<String, serde_json::Value>,
 }
 
 impl ModrinthIndex {
+    /// Names of the fields of the index and its files that aren't part of the known format, with
+    /// file fields prefixed by `files[].`.
+    pub(crate) fn unknown_fields(&self) -> BTreeSet<String> {
+        let mut fields: BTreeSet<String> = self.other_fields.keys().cloned().collect();
+        for file in &self.files {
+            fields.extend(
+                file.other_fields
+                    .keys()
+                    .map(|field| format!("files[].{field}")),
+            );
+        }
+        fields
+    }
+
     pub(crate) fn print_info(&self) {
-        println!("{} version {}", self.name, self.version_id);
+        log_println!("{} version {}", self.name, self.version_id);
         if let Some(summary) = &self.summary {
-            println!("\n{summary}");
+            log_println!("\n{summary}");
         }
-        println!("\nDependencies:");
+        log_println!("\nDependencies:");
         for (dep_id, dep_ver) in &self.dependencies {
-            println!("{}: {}", dep_id.as_ref(), dep_ver);
+            log_println!("{}: {}", dep_id.as_ref(), dep_ver);
+        }
+        if let Some(java) = self.java_version() {
+            log_println!("\nThis pack needs Java {java}");
+        }
+        if self.files.is_empty() {
+            log_println!("\nFiles: none, the modpack only consists of overrides");
+            return;
+        }
+        log_println!("\nFiles:");
+        for (folder, count) in self.files_by_folder() {
+            log_println!("{folder}: {count}");
+        }
+    }
+
+    /// Major version of Java that the pack's Minecraft version needs, if it is a release version.
+    pub(crate) fn java_version(&self) -> Option<u32> {
+        let minecraft = self.dependencies.get(&ModpackDependencyId::Minecraft)?;
+        java_version_for(&minecraft.to_string())
+    }
+
+    /// Number of files in each top level folder, like `mods` or `resourcepacks`, which tells what
+    /// kind of content they are.
+    fn files_by_folder(&self) -> BTreeMap<String, usize> {
+        let mut folders = BTreeMap::new();
+        for file in &self.files {
+            let folder = match file
+                .path
+                .parent()
+                .and_then(|parent| parent.components().next())
+            {
+                Some(component) => component.as_os_str().to_string_lossy().into_owned(),
+                None => "(top level)".to_string(),
+            };
+            *folders.entry(folder).or_default() += 1;
+        }
+        folders
+    }
+
+    /// A `<name>-<version>` folder name that is safe to use on any filesystem.
+    pub(crate) fn folder_name(&self) -> String {
+        let name: String = format!("{}-{}", self.name, self.version_id)
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        // Leading dots would hide the folder or turn it into `.`/`..`, and Windows doesn't allow
+        // trailing dots and spaces.
+        let name = name.trim_matches(|c| c == '.' || c == ' ');
+        if name.is_empty() {
+            "modpack".to_string()
+        } else {
+            name.to_string()
         }
     }
 }
 
+/// Java major version needed by each range of Minecraft releases, as `(minor, patch)` of the first
+/// release of the range and the Java version.
+const JAVA_VERSIONS: &[((u32, u32), u32)] = &[((20, 5), 21), ((18, 0), 17), ((17, 0), 16)];
+
+/// Java major version that a Minecraft release version like `1.20.1` needs. Snapshots and other
+/// version names are not known.
+fn java_version_for(minecraft: &str) -> Option<u32> {
+    let mut parts = minecraft.split('.').map(|part| part.parse::<u32>().ok());
+    let (Some(Some(1)), Some(Some(minor))) = (parts.next(), parts.next()) else {
+        return None;
+    };
+    let patch = match parts.next() {
+        Some(patch) => patch?,
+        None => 0,
+    };
+    Some(
+        JAVA_VERSIONS
+            .iter()
+            .find(|(first, _)| (minor, patch) >= *first)
+            .map_or(8, |&(_, java)| java),
+    )
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ModpackFile {
@@ -38,8 +147,12 @@ pub struct ModpackFile {
     pub env: Option<FileEnv>,
     pub downloads: Vec<Url>,
     pub file_size: u32,
+    /// Fields that aren't part of the known format.
+    #[serde(flatten)]
+    pub other_fields: HashMap<String, serde_json::Value>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 pub struct FileHashes {
     #[serde(deserialize_with = "hex::deserialize")]
@@ -64,7 +177,7 @@ pub enum EnvRequirement {
     Unsupported,
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize, AsRefStr)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize, Serialize, AsRefStr)]
 #[serde(rename_all = "kebab-case")]
 pub enum ModpackDependencyId {
     Minecraft,
@@ -72,3 +185,74 @@ pub enum ModpackDependencyId {
     FabricLoader,
     QuiltLoader,
 }
+
+/// Version of a dependency. Not every loader follows semver, some Forge versions for example
+/// have four components, so versions that don't parse are kept as they are.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum DependencyVersion {
+    Semver(Version),
+    Other(String),
+}
+
+impl fmt::Display for DependencyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Semver(version) => version.fmt(f),
+            Self::Other(version) => f.write_str(version),
+        }
+    }
+}
+
+/// A version of a project as returned by the Modrinth API, with only the fields needed to match
+/// its files.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModrinthVersion {
+    pub id: String,
+    pub project_id: String,
+    pub files: Vec<ModrinthVersionFile>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModrinthVersionFile {
+    pub hashes: ModrinthVersionFileHashes,
+    pub url: Url,
+    pub filename: String,
+}
+
+/// Hex-encoded hashes of a published file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModrinthVersionFileHashes {
+    pub sha1: String,
+    pub sha512: String,
+}
+
+/// Optional files chosen to be downloaded, saved so that the choice can be repeated without
+/// prompts.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct OptionalSelection {
+    pub include: Vec<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum SelectionError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Invalid selection file: {0}")]
+    SerdeError(#[from] serde_json::Error),
+}
+
+impl OptionalSelection {
+    pub(crate) fn load(path: &Path) -> Result<Self, SelectionError> {
+        let data = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), SelectionError> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
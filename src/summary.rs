@@ -0,0 +1,106 @@
+//! Machine-readable report of an install, written with `--summary-json`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    download::DownloadReport,
+    log_file,
+    schemas::{DependencyVersion, ModpackDependencyId, ModpackFile, ModrinthIndex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FileStatus {
+    Downloaded,
+    /// Already present and valid.
+    Skipped,
+    /// Failed the hash check and was deleted.
+    Failed,
+    /// Larger than `--max-file-size`, left to download manually.
+    Oversized,
+    /// Not downloaded because the install failed first.
+    NotInstalled,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct InstallSummary<'a> {
+    name: &'a str,
+    version: &'a str,
+    format_version: u32,
+    dependencies: &'a HashMap<ModpackDependencyId, DependencyVersion>,
+    /// Major version of Java the pack's Minecraft version needs.
+    java_version: Option<u32>,
+    /// Status of every file to download, keyed by its path in the output directory.
+    files: BTreeMap<String, FileStatus>,
+    bytes_downloaded: u64,
+    /// Extracted override files, mapped to the override folder they came from.
+    overrides: BTreeMap<String, &'a str>,
+    duration_secs: f64,
+    /// Every warning and error printed during the install.
+    warnings: Vec<String>,
+    /// Why the install failed, if it did.
+    error: Option<String>,
+}
+
+impl<'a> InstallSummary<'a> {
+    pub(crate) fn new(
+        index: &'a ModrinthIndex,
+        output_dir: &Path,
+        report: &DownloadReport,
+        oversized: &[ModpackFile],
+        extracted: &'a HashMap<PathBuf, String>,
+        duration: Duration,
+        error: Option<String>,
+    ) -> Self {
+        let relative = |path: &Path| {
+            path.strip_prefix(output_dir)
+                .unwrap_or(path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        let mut files = BTreeMap::new();
+        // A file that was downloaded and then failed the deferred hash check ends up failed
+        for (paths, status) in [
+            (&report.downloaded, FileStatus::Downloaded),
+            (&report.skipped, FileStatus::Skipped),
+            (&report.hash_failures, FileStatus::Failed),
+            (&report.not_downloaded, FileStatus::NotInstalled),
+        ] {
+            for path in paths {
+                files.insert(relative(path), status);
+            }
+        }
+        for file in oversized {
+            files.insert(relative(&file.path), FileStatus::Oversized);
+        }
+        Self {
+            name: &index.name,
+            version: &index.version_id,
+            format_version: index.format_version,
+            dependencies: &index.dependencies,
+            java_version: index.java_version(),
+            files,
+            bytes_downloaded: report.mirror_stats.values().map(|stats| stats.bytes).sum(),
+            overrides: extracted
+                .iter()
+                .map(|(path, folder)| (relative(path), folder.as_str()))
+                .collect(),
+            duration_secs: duration.as_secs_f64(),
+            warnings: log_file::error_lines(),
+            error,
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)
+    }
+}
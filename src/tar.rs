@@ -0,0 +1,219 @@
+//! Reader for tar archives, plain or gzipped.
+//!
+//! Modpacks are small enough to be held in memory once decompressed, which allows reading entries
+//! in any order like in a zip file.
+
+use std::{io::Read, path::Path};
+
+use flate2::read::MultiGzDecoder;
+use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::archive::{Archive, ArchiveEntry, ArchiveError, EntryKind};
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Error)]
+pub(crate) enum TarError {
+    #[error("Failed to decompress the archive: {0}")]
+    Gzip(std::io::Error),
+    #[error("Not a zip or tar archive")]
+    NotAnArchive,
+    #[error("The tar archive is truncated or corrupted at byte {0}")]
+    Corrupted(usize),
+}
+
+#[derive(Debug)]
+struct TarEntry {
+    name: String,
+    kind: EntryKind,
+    /// Where the content starts in the decompressed archive.
+    offset: usize,
+    size: usize,
+    link_target: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct TarArchive {
+    data: Vec<u8>,
+    entries: Vec<TarEntry>,
+}
+
+impl TarArchive {
+    pub(crate) async fn open(path: &Path) -> Result<Self, ArchiveError> {
+        let mut data = tokio::fs::read(path).await?;
+        if data.starts_with(&[0x1f, 0x8b]) {
+            data = tokio::task::spawn_blocking(move || {
+                let mut decompressed = Vec::new();
+                MultiGzDecoder::new(data.as_slice())
+                    .read_to_end(&mut decompressed)
+                    .map(|_| decompressed)
+            })
+            .await
+            .expect("Decompression task panicked")
+            .map_err(TarError::Gzip)?;
+        }
+        let entries = parse_entries(&data)?;
+        Ok(Self { data, entries })
+    }
+}
+
+impl Archive for TarArchive {
+    fn entries(&self) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        Ok(self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| ArchiveEntry {
+                index,
+                name: entry.name.clone(),
+                kind: entry.kind,
+                size: entry.size as u64,
+                crc32: None,
+            })
+            .collect())
+    }
+
+    async fn copy_entry<W: AsyncWrite + Unpin>(
+        &self,
+        index: usize,
+        out: &mut W,
+    ) -> Result<u64, ArchiveError> {
+        let entry = &self.entries[index];
+        let content = if entry.kind == EntryKind::Symlink {
+            entry.link_target.as_bytes()
+        } else {
+            &self.data[entry.offset..entry.offset + entry.size]
+        };
+        out.write_all(content).await?;
+        // A file only reports errors writing its last chunk when flushed
+        out.flush().await?;
+        Ok(content.len() as u64)
+    }
+}
+
+/// Splits a tar archive into its entries. Understands ustar, GNU long names and the path fields of
+/// pax headers, which covers what common tar tools write.
+fn parse_entries(data: &[u8]) -> Result<Vec<TarEntry>, TarError> {
+    if data.len() < BLOCK_SIZE || !is_header(&data[..BLOCK_SIZE]) {
+        return Err(TarError::NotAnArchive);
+    }
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    // Set by GNU long name and pax headers for the entry that follows them
+    let mut long_name = None;
+    let mut long_link = None;
+    while offset + BLOCK_SIZE <= data.len() {
+        let header = &data[offset..offset + BLOCK_SIZE];
+        // The archive ends with zeroed blocks
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !is_header(header) {
+            return Err(TarError::Corrupted(offset));
+        }
+        let size = parse_size(&header[124..136]).ok_or(TarError::Corrupted(offset))?;
+        let content_start = offset + BLOCK_SIZE;
+        // Binary sizes can be large enough to overflow
+        let content_end = content_start
+            .checked_add(size)
+            .ok_or(TarError::Corrupted(offset))?;
+        let content = data
+            .get(content_start..content_end)
+            .ok_or(TarError::Corrupted(offset))?;
+        offset = content_start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+        match header[156] {
+            b'L' => long_name = Some(field(content)),
+            b'K' => long_link = Some(field(content)),
+            b'x' => {
+                for (key, value) in pax_records(content) {
+                    match key {
+                        "path" => long_name = Some(value.to_string()),
+                        "linkpath" => long_link = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            // Global pax headers don't name anything
+            b'g' => {}
+            typeflag => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = field(&header[..100]);
+                    let prefix = field(&header[345..500]);
+                    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        format!("{prefix}/{name}")
+                    } else {
+                        name
+                    }
+                });
+                let link_target = long_link.take().unwrap_or_else(|| field(&header[157..257]));
+                let kind = match typeflag {
+                    b'0' | b'\0' | b'7' => EntryKind::File,
+                    b'5' => EntryKind::Directory,
+                    b'2' => EntryKind::Symlink,
+                    _ => EntryKind::Other,
+                };
+                entries.push(TarEntry {
+                    // Archives made from inside a directory name their entries `./...`
+                    name: name.strip_prefix("./").unwrap_or(&name).to_string(),
+                    kind,
+                    offset: content_start,
+                    size,
+                    link_target,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether `header` has a valid checksum, which is the sum of its bytes with the checksum field
+/// counted as spaces.
+fn is_header(header: &[u8]) -> bool {
+    let Some(expected) = parse_octal(&header[148..156]) else {
+        return false;
+    };
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as usize)
+        .sum();
+    sum == expected
+}
+
+/// Parses a numeric header field, which is octal text unless the high bit of the first byte marks
+/// it as a big-endian binary number.
+fn parse_size(field: &[u8]) -> Option<usize> {
+    if field[0] & 0x80 != 0 {
+        field[1..].iter().try_fold(0usize, |size, &b| {
+            size.checked_mul(256)?.checked_add(b as usize)
+        })
+    } else {
+        parse_octal(field)
+    }
+}
+
+fn parse_octal(field: &[u8]) -> Option<usize> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    usize::from_str_radix(text, 8).ok()
+}
+
+/// A NUL terminated text field.
+fn field(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// The `key=value` pairs of pax extended header records, which look like `<length> key=value\n`.
+fn pax_records(content: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    let text = std::str::from_utf8(content).unwrap_or_default();
+    text.lines().filter_map(|line| {
+        let (_, record) = line.split_once(' ')?;
+        record.split_once('=')
+    })
+}
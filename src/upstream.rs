@@ -0,0 +1,121 @@
+//! Cross-check of the files in the index against the versions published on Modrinth, which catches
+//! modpacks whose index was edited to point at something else.
+
+use futures_util::stream::StreamExt;
+use reqwest::{Client, StatusCode};
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    download::MODRINTH_CDN,
+    log_file::{log_eprintln, log_println},
+    schemas::{ModpackFile, ModrinthVersion},
+};
+
+/// Where the Modrinth API is reached, overridable for tests.
+pub(crate) const MODRINTH_API: &str = "https://api.modrinth.com";
+
+#[derive(Debug, Error)]
+pub(crate) enum UpstreamError {
+    #[error("Request to {url} failed: {source}")]
+    Request { url: Url, source: reqwest::Error },
+    #[error("Unexpected response from {url}: {source}")]
+    Parse { url: Url, source: serde_json::Error },
+    #[error("Request to {url} failed. Status code: {status}")]
+    Status { url: Url, status: StatusCode },
+}
+
+/// Outcome of checking the files against Modrinth.
+#[derive(Debug, Default)]
+pub(crate) struct UpstreamReport {
+    pub published: usize,
+    /// Files claiming to come from the Modrinth CDN that Modrinth doesn't know.
+    pub unpublished: Vec<String>,
+    /// Files Modrinth doesn't know that are hosted elsewhere, which packs are allowed to have.
+    pub external: Vec<String>,
+}
+
+impl UpstreamReport {
+    pub(crate) fn is_ok(&self) -> bool {
+        self.unpublished.is_empty()
+    }
+
+    pub(crate) fn print(&self) {
+        for path in &self.unpublished {
+            log_eprintln!(
+                "{path} is downloaded from the Modrinth CDN, but doesn't match any published file"
+            );
+        }
+        for path in &self.external {
+            log_eprintln!("Warning: {path} is not published on Modrinth and can't be checked");
+        }
+        log_println!(
+            "{} published, {} not published, {} hosted elsewhere",
+            self.published,
+            self.unpublished.len(),
+            self.external.len()
+        );
+    }
+}
+
+/// Looks up the SHA-512 hash of every file with Modrinth's version API, with up to `jobs` requests
+/// at once. A file counts as published if the version found has a file with both of its hashes.
+pub(crate) async fn verify_upstream(
+    files: &[ModpackFile],
+    client: &Client,
+    api: &Url,
+    jobs: usize,
+) -> Result<UpstreamReport, UpstreamError> {
+    let mut results = futures::stream::iter(files)
+        .map(|file| async move {
+            let sha512 = hex::encode(file.hashes.sha512);
+            let mut url = api
+                .join(&format!("v2/version_file/{sha512}"))
+                .expect("A hex hash is a valid path segment");
+            url.set_query(Some("algorithm=sha512"));
+            let request_error = |source| UpstreamError::Request {
+                url: url.clone(),
+                source,
+            };
+            let response = client
+                .get(url.clone())
+                .send()
+                .await
+                .map_err(request_error)?;
+            let published = match response.status() {
+                StatusCode::NOT_FOUND => false,
+                status if status.is_success() => {
+                    let body = response.bytes().await.map_err(request_error)?;
+                    let version: ModrinthVersion = serde_json::from_slice(&body)
+                        .map_err(|source| UpstreamError::Parse { url, source })?;
+                    let sha1 = hex::encode(file.hashes.sha1);
+                    version.files.iter().any(|published| {
+                        published.hashes.sha512 == sha512 && published.hashes.sha1 == sha1
+                    })
+                }
+                status => return Err(UpstreamError::Status { url, status }),
+            };
+            Ok((file, published))
+        })
+        .buffer_unordered(jobs);
+
+    let mut report = UpstreamReport::default();
+    while let Some(result) = results.next().await {
+        let (file, published) = result?;
+        let path = file.path.to_string_lossy().into_owned();
+        if published {
+            report.published += 1;
+        } else if file
+            .downloads
+            .iter()
+            .any(|url| url.host_str() == Some(MODRINTH_CDN))
+        {
+            report.unpublished.push(path);
+        } else {
+            report.external.push(path);
+        }
+    }
+    report.unpublished.sort();
+    report.external.sort();
+    Ok(report)
+}
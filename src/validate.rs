@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use crate::{
+    archive::Archive, check_host, get_index_data, log_file::log_println, sanitize_file_path,
+    schemas::SUPPORTED_FORMAT_VERSION,
+};
+
+/// Checks the index of a modpack without downloading anything, printing every problem found.
+///
+/// Hash lengths and URL syntax are checked while parsing the index. Returns the number of problems.
+pub(crate) async fn validate_pack(archive: &impl Archive, skip_host_check: bool) -> usize {
+    let index = match get_index_data(archive).await {
+        Ok(index) => index,
+        Err(why) => {
+            log_println!("error: {why}");
+            return 1;
+        }
+    };
+
+    let mut problems = 0;
+    let mut report = |path: &str, problem: String| {
+        log_println!("error: {path}: {problem}");
+        problems += 1;
+    };
+
+    // Like the installer, newer versions are accepted with a warning
+    if index.format_version > SUPPORTED_FORMAT_VERSION {
+        log_println!(
+            "warning: formatVersion: version {} is newer than the supported version {SUPPORTED_FORMAT_VERSION}",
+            index.format_version
+        );
+    } else if index.format_version < SUPPORTED_FORMAT_VERSION {
+        report(
+            "formatVersion",
+            format!("unsupported version {}", index.format_version),
+        );
+    }
+    if index.game != "minecraft" {
+        report("game", format!("unsupported game {}", index.game));
+    }
+
+    let mut paths = HashMap::new();
+    for file in &index.files {
+        let shown_path = file.path.to_string_lossy();
+        if sanitize_file_path(&file.path).is_none() {
+            report(&shown_path, "the path is absolute or empty".to_string());
+        }
+        if let Some(other_hashes) = paths.insert(&file.path, &file.hashes.sha512) {
+            if other_hashes != &file.hashes.sha512 {
+                report(
+                    &shown_path,
+                    "listed more than once with different hashes".to_string(),
+                );
+            }
+        }
+        if file.file_size == 0 {
+            report(&shown_path, "the file size is 0".to_string());
+        }
+        if file.downloads.is_empty() {
+            report(&shown_path, "there are no download URLs".to_string());
+        }
+        for url in &file.downloads {
+            if !matches!(url.scheme(), "http" | "https") {
+                report(&shown_path, format!("{url} is not an http(s) URL"));
+            } else if !skip_host_check {
+                if let Err(why) = check_host(url) {
+                    report(&shown_path, why.to_string());
+                }
+            }
+        }
+    }
+
+    log_println!("Checked {} files", index.files.len());
+    problems
+}
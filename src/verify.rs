@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use futures_util::stream::StreamExt;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::{
+    display_path,
+    hash_checks::check_hashes,
+    log_file::{log_eprintln, log_println},
+    sanitize_path_check,
+    schemas::ModpackFile,
+    UnsafePathError,
+};
+
+/// Outcome of checking already installed files against the index.
+#[derive(Debug, Default)]
+pub(crate) struct VerifyReport {
+    pub valid: usize,
+    pub missing: Vec<PathBuf>,
+    pub corrupted: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    pub(crate) fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty()
+    }
+
+    pub(crate) fn print(&self, output_dir: &Path, absolute_paths: bool) {
+        for path in &self.missing {
+            log_eprintln!(
+                "Missing file {}",
+                display_path(path, output_dir, absolute_paths)
+            );
+        }
+        for path in &self.corrupted {
+            log_eprintln!(
+                "Corrupted file {}",
+                display_path(path, output_dir, absolute_paths)
+            );
+        }
+        log_println!(
+            "{} valid, {} missing, {} corrupted",
+            self.valid,
+            self.missing.len(),
+            self.corrupted.len()
+        );
+    }
+}
+
+enum FileStatus {
+    Valid,
+    Missing,
+    Corrupted,
+}
+
+/// Checks the hashes of `files` installed in `output_dir`, hashing up to `jobs` files at once.
+/// Paths in messages are shown relative to `output_dir`, unless `absolute_paths` is set.
+///
+/// Every file is hashed in its own task so that the work is spread over the runtime's threads.
+pub(crate) async fn verify_files(
+    files: Vec<ModpackFile>,
+    output_dir: &Path,
+    jobs: usize,
+    absolute_paths: bool,
+) -> Result<VerifyReport, UnsafePathError> {
+    for file in &files {
+        sanitize_path_check(&output_dir.join(&file.path), output_dir)?;
+    }
+
+    let bar = ProgressBar::with_draw_target(Some(files.len() as u64), ProgressDrawTarget::stdout())
+        .with_message("Verifying files")
+        .with_style(
+            ProgressStyle::default_bar()
+                .template("{msg}\n{spinner} [{elapsed_precise}] [{wide_bar}] {pos}/{len}")
+                .expect("Incorrect template provided")
+                .progress_chars("#> "),
+        );
+
+    let mut results = futures::stream::iter(files)
+        .map(|file| {
+            let path = output_dir.join(&file.path);
+            let shown_path = display_path(&path, output_dir, absolute_paths);
+            tokio::spawn(async move {
+                let status = if !path.is_file() {
+                    FileStatus::Missing
+                } else {
+                    match check_hashes(&file.hashes, &path).await {
+                        Ok(true) => FileStatus::Valid,
+                        Ok(false) => FileStatus::Corrupted,
+                        Err(why) => {
+                            log_eprintln!("Failed to read file {shown_path}: {why}");
+                            FileStatus::Corrupted
+                        }
+                    }
+                };
+                (path, status)
+            })
+        })
+        .buffer_unordered(jobs);
+
+    let mut report = VerifyReport::default();
+    while let Some(result) = results.next().await {
+        let (path, status) = result.expect("Verification task panicked");
+        match status {
+            FileStatus::Valid => report.valid += 1,
+            FileStatus::Missing => report.missing.push(path),
+            FileStatus::Corrupted => report.corrupted.push(path),
+        }
+        bar.inc(1);
+    }
+    bar.finish_with_message("Verified files");
+    Ok(report)
+}
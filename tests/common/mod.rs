@@ -0,0 +1,344 @@
+//! Helpers shared by the integration tests: a mock download server, modpack building from the
+//! fixtures in `tests/fixtures` and running the binary.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use async_zip::{tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use sha1::{Digest, Sha1};
+use sha2::Sha512;
+
+pub fn fixture_dir(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// All files under `dir`, keyed by their path relative to it with `/` as the separator.
+pub fn read_dir_recursive(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let relative = path.strip_prefix(dir).unwrap();
+                let name = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((name, fs::read(&path).unwrap()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Minimal HTTP server handing out fixed responses, one connection per request.
+pub struct MockServer {
+    pub base_url: String,
+    requests: Arc<Mutex<HashMap<String, usize>>>,
+    authorizations: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MockServer {
+    /// Serves `files` under their keys, answering anything else with 404.
+    pub fn start(files: Vec<(String, Vec<u8>)>) -> Self {
+        Self::serve(files, None, false)
+    }
+
+    /// Like [`MockServer::start`], sending only the first half of every file and then nothing for
+    /// a minute, like a stuck mirror.
+    pub fn start_stalling(files: Vec<(String, Vec<u8>)>) -> Self {
+        Self::serve(files, None, true)
+    }
+
+    /// Like [`MockServer::start`], sending every file gzip-compressed with `Content-Encoding`.
+    pub fn start_gzipped(files: Vec<(String, Vec<u8>)>) -> Self {
+        let files = files
+            .into_iter()
+            .map(|(path, body)| {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&body).unwrap();
+                (path, encoder.finish().unwrap())
+            })
+            .collect();
+        Self::serve(files, Some("gzip"), false)
+    }
+
+    fn serve(
+        files: Vec<(String, Vec<u8>)>,
+        content_encoding: Option<&'static str>,
+        stall: bool,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let files: Arc<HashMap<String, Vec<u8>>> = Arc::new(files.into_iter().collect());
+        let requests = Arc::new(Mutex::new(HashMap::new()));
+        let requests_clone = requests.clone();
+        let authorizations = Arc::new(Mutex::new(HashMap::new()));
+        let authorizations_clone = authorizations.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let files = files.clone();
+                let requests = requests_clone.clone();
+                let authorizations = authorizations_clone.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    // Skip the headers, except for the one with credentials
+                    let mut authorization = None;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("authorization") {
+                                authorization = Some(value.trim().to_string());
+                            }
+                        }
+                    }
+                    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+                    // Requests sent to a proxy have the whole URL
+                    let target = match target.strip_prefix("http://") {
+                        Some(url) => url.find('/').map_or("/", |start| &url[start..]),
+                        None => target,
+                    };
+                    let path = target.trim_start_matches('/').to_string();
+                    *requests.lock().unwrap().entry(path.clone()).or_default() += 1;
+                    if let Some(authorization) = authorization {
+                        authorizations
+                            .lock()
+                            .unwrap()
+                            .insert(path.clone(), authorization);
+                    }
+                    let (status, body, encoding) = match files.get(&path) {
+                        Some(body) => ("200 OK", body.as_slice(), content_encoding),
+                        None => ("404 Not Found", b"not found".as_slice(), None),
+                    };
+                    let encoding = encoding
+                        .map(|encoding| format!("Content-Encoding: {encoding}\r\n"))
+                        .unwrap_or_default();
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\n{encoding}Connection: close\r\n\r\n",
+                        body.len()
+                    );
+                    if stall && status == "200 OK" {
+                        let _ = stream.write_all(&body[..body.len() / 2]);
+                        thread::sleep(Duration::from_secs(60));
+                        return;
+                    }
+                    let _ = stream.write_all(body);
+                });
+            }
+        });
+        Self {
+            base_url,
+            requests,
+            authorizations,
+        }
+    }
+
+    /// Serves the `files/` directory of a fixture.
+    pub fn for_fixture(name: &str) -> Self {
+        Self::start(read_dir_recursive(&fixture_dir(name).join("files")))
+    }
+
+    /// How many times `path` was requested.
+    pub fn requests(&self, path: &str) -> usize {
+        self.requests
+            .lock()
+            .unwrap()
+            .get(path)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The `Authorization` header last sent when requesting `path`.
+    pub fn authorization(&self, path: &str) -> Option<String> {
+        self.authorizations.lock().unwrap().get(path).cloned()
+    }
+}
+
+/// Appends a copy of the first file of `index`, installed to `path` and downloaded from `path`
+/// on `base_url`. The copy has the same hashes and size.
+pub fn add_file_copy(index: &mut serde_json::Value, base_url: &str, path: &str) {
+    let mut copy = index["files"][0].clone();
+    copy["path"] = path.into();
+    copy["downloads"] = serde_json::json!([format!("{base_url}/{path}")]);
+    index["files"].as_array_mut().unwrap().push(copy);
+}
+
+/// Fills in the placeholders of a fixture's index template, see `tests/fixtures/README.md`.
+pub fn render_index(fixture: &str, base_url: &str) -> String {
+    let dir = fixture_dir(fixture);
+    let template = fs::read_to_string(dir.join("modrinth.index.json")).unwrap();
+    let mut rendered = String::new();
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = start + rest[start..].find("}}").unwrap();
+        let placeholder = &rest[start + 2..end];
+        let value = match placeholder.split_once(':') {
+            None if placeholder == "base_url" => base_url.to_string(),
+            Some((kind, path)) => {
+                let data = fs::read(dir.join("files").join(path)).unwrap();
+                match kind {
+                    "sha1" => hex::encode(Sha1::digest(&data)),
+                    "sha512" => hex::encode(Sha512::digest(&data)),
+                    "size" => data.len().to_string(),
+                    _ => panic!("Unknown placeholder {placeholder}"),
+                }
+            }
+            None => panic!("Unknown placeholder {placeholder}"),
+        };
+        rendered.push_str(&value);
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Entries of a modpack file, written out with [`Pack::write`].
+#[derive(Debug, Clone, Default)]
+pub struct Pack {
+    pub entries: Vec<(String, Vec<u8>)>,
+}
+
+impl Pack {
+    /// The fixture's rendered index and every directory except `files/`.
+    pub fn from_fixture(name: &str, base_url: &str) -> Self {
+        let dir = fixture_dir(name);
+        let mut entries = vec![(
+            "modrinth.index.json".to_string(),
+            render_index(name, base_url).into_bytes(),
+        )];
+        entries.extend(
+            read_dir_recursive(&dir)
+                .into_iter()
+                .filter(|(name, _)| name.contains('/') && !name.starts_with("files/")),
+        );
+        Self { entries }
+    }
+
+    /// A pack with just the given index.
+    pub fn with_index(index: &str) -> Self {
+        Self {
+            entries: vec![("modrinth.index.json".to_string(), index.as_bytes().to_vec())],
+        }
+    }
+
+    pub fn entry(mut self, name: &str, data: &[u8]) -> Self {
+        self.entries.push((name.to_string(), data.to_vec()));
+        self
+    }
+
+    pub fn write(&self, path: &Path) {
+        self.write_with(path, |name| {
+            ZipEntryBuilder::new(name.into(), Compression::Deflate)
+        })
+    }
+
+    /// Writes the pack as a gzipped ustar archive instead of a zip file.
+    pub fn write_tar_gz(&self, path: &Path) {
+        let mut tar = Vec::new();
+        for (name, data) in &self.entries {
+            let mut header = [0u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[108..116].copy_from_slice(b"0000000\0");
+            header[116..124].copy_from_slice(b"0000000\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+            header[136..148].copy_from_slice(b"00000000000\0");
+            header[156] = b'0';
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[263..265].copy_from_slice(b"00");
+            // The checksum is computed with its own field filled with spaces
+            header[148..156].copy_from_slice(b"        ");
+            let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+            header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+            tar.extend_from_slice(&header);
+            tar.extend_from_slice(data);
+            tar.resize(tar.len().div_ceil(512) * 512, 0);
+        }
+        tar.resize(tar.len() + 1024, 0);
+        let mut encoder = flate2::write::GzEncoder::new(
+            fs::File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&tar).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    /// Writes the pack, letting `entry` pick the compression and attributes of each entry.
+    pub fn write_with(&self, path: &Path, entry: impl Fn(&str) -> ZipEntryBuilder) {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let file = tokio::fs::File::create(path).await.unwrap();
+            let mut writer = ZipFileWriter::with_tokio(file);
+            for (name, data) in &self.entries {
+                writer.write_entry_whole(entry(name), data).await.unwrap();
+            }
+            writer.close().await.unwrap();
+        })
+    }
+}
+
+/// Writes the pack of a fixture downloading from `base_url` into a new temporary directory,
+/// returning the directory and the path of the pack in it.
+pub fn write_fixture_pack(fixture: &str, base_url: &str) -> (tempfile::TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join(format!("{fixture}.mrpack"));
+    Pack::from_fixture(fixture, base_url).write(&pack_path);
+    (dir, pack_path)
+}
+
+/// Serves the files of a fixture and writes its pack like [`write_fixture_pack`].
+pub fn serve_fixture_pack(fixture: &str) -> (MockServer, tempfile::TempDir, PathBuf) {
+    let server = MockServer::for_fixture(fixture);
+    let (dir, pack_path) = write_fixture_pack(fixture, &server.base_url);
+    (server, dir, pack_path)
+}
+
+/// Runs the downloader with `args`, without a terminal attached.
+pub fn run(args: &[&str]) -> Output {
+    run_with_env(args, &[])
+}
+
+/// Like [`run`], with the additional environment variables `env`.
+pub fn run_with_env(args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mrpack-downloader"))
+        .args(args)
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+pub fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "The downloader failed.\nstdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,1853 @@
+mod common;
+
+use std::fs;
+
+use async_zip::{Compression, ZipEntryBuilder};
+use common::{
+    add_file_copy, assert_success, fixture_dir, read_dir_recursive, render_index, run,
+    run_with_env, serve_fixture_pack, write_fixture_pack, MockServer, Pack,
+};
+use sha2::{Digest, Sha512};
+
+#[test]
+fn installs_simple_pack() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    let fixture = fixture_dir("simple_pack");
+    assert_eq!(
+        fs::read(output_dir.join("mods/example-mod.jar")).unwrap(),
+        fs::read(fixture.join("files/mods/example-mod.jar")).unwrap()
+    );
+    assert_eq!(
+        fs::read(output_dir.join("config/example.toml")).unwrap(),
+        fs::read(fixture.join("overrides/config/example.toml")).unwrap()
+    );
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+}
+
+#[test]
+fn skips_valid_existing_files() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+    let args = [
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ];
+
+    assert_success(&run(&args));
+    assert_success(&run(&args));
+
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+}
+
+#[test]
+fn fails_on_hash_mismatch() {
+    // The index describes the fixture file, but the server hands out something else of the same
+    // size, so only the hash check can catch it.
+    let size = fs::metadata(fixture_dir("simple_pack").join("files/mods/example-mod.jar"))
+        .unwrap()
+        .len() as usize;
+    let server = MockServer::start(vec![("mods/example-mod.jar".to_string(), vec![b'x'; size])]);
+    let (dir, pack_path) = write_fixture_pack("simple_pack", &server.base_url);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--max-retries",
+        "1",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+    assert_eq!(server.requests("mods/example-mod.jar"), 2);
+}
+
+#[test]
+fn installs_from_compressing_server() {
+    // Content-Length is the compressed size, which must not be mistaken for a cut off response
+    let server = MockServer::start_gzipped(read_dir_recursive(
+        &fixture_dir("simple_pack").join("files"),
+    ));
+    let (dir, pack_path) = write_fixture_pack("simple_pack", &server.base_url);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert_eq!(
+        fs::read(output_dir.join("mods/example-mod.jar")).unwrap(),
+        fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap()
+    );
+}
+
+#[test]
+fn warns_about_wrong_file_size() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    let size = index["files"][0]["fileSize"].as_u64().unwrap();
+    index["files"][0]["fileSize"] = (size + 1).into();
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "Warning: mods/example-mod.jar is {size} bytes, but the modpack says {}",
+        size + 1
+    )));
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+}
+
+#[test]
+fn names_file_when_every_mirror_fails() {
+    let server = MockServer::start(Vec::new());
+    let (dir, pack_path) = write_fixture_pack("simple_pack", &server.base_url);
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = stderr.lines().last().unwrap();
+    assert!(error.starts_with("Error: Download failed: Every mirror of"));
+    assert!(error.ends_with("mods/example-mod.jar failed"));
+}
+
+#[test]
+fn finishes_other_downloads_when_retry_budget_runs_out() {
+    let good = fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap();
+    // Same size as the real file, so only the hash check fails and needs a retry
+    let server = MockServer::start(vec![
+        ("mods/example-mod.jar".to_string(), vec![b'x'; good.len()]),
+        ("mods/other-mod.jar".to_string(), good),
+    ]);
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    add_file_copy(&mut index, &server.base_url, "mods/other-mod.jar");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--max-total-retries",
+        "0",
+    ]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Not downloaded, out of retries: mods/example-mod.jar"));
+    assert!(stderr.contains("1 files were not downloaded"));
+    assert!(output_dir.join("mods/other-mod.jar").is_file());
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn installs_through_symlinked_output_dir() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let real_dir = dir.path().join("real");
+    fs::create_dir(&real_dir).unwrap();
+    std::os::unix::fs::symlink(&real_dir, dir.path().join("link")).unwrap();
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("link/out").to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(real_dir.join("out/mods/example-mod.jar").is_file());
+    assert!(real_dir.join("out/config/example.toml").is_file());
+}
+
+#[cfg(unix)]
+#[test]
+fn recreates_symlinks_inside_output_dir() {
+    use async_zip::AttributeCompatibility;
+
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::from_fixture("simple_pack", &server.base_url)
+        .entry("overrides/link.toml", b"config/example.toml")
+        .entry("overrides/escape.toml", b"../../outside.toml")
+        .write_with(&pack_path, |name| {
+            let builder = ZipEntryBuilder::new(name.into(), Compression::Stored);
+            if name.ends_with("link.toml") || name.ends_with("escape.toml") {
+                builder
+                    .attribute_compatibility(AttributeCompatibility::Unix)
+                    .unix_permissions(0o120777)
+            } else {
+                builder
+            }
+        });
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--symlinks",
+        "create",
+    ]);
+
+    assert_success(&output);
+    let link = output_dir.join("link.toml");
+    assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+    assert_eq!(
+        fs::read_link(&link).unwrap(),
+        std::path::Path::new("config/example.toml")
+    );
+    assert!(output_dir.join("escape.toml").symlink_metadata().is_err());
+}
+
+#[test]
+fn rejects_absolute_and_empty_paths() {
+    for path in ["/etc/passwd", "", "../.."] {
+        let dir = tempfile::tempdir().unwrap();
+        let pack_path = dir.path().join("bad.mrpack");
+        let index = serde_json::json!({
+            "formatVersion": 1,
+            "game": "minecraft",
+            "versionId": "1.0.0",
+            "name": "Bad Pack",
+            "files": [{
+                "path": path,
+                "hashes": {"sha1": "00".repeat(20), "sha512": "00".repeat(64)},
+                "downloads": ["http://127.0.0.1:1/file"],
+                "fileSize": 1
+            }],
+            "dependencies": {}
+        });
+        Pack::with_index(&index.to_string()).write(&pack_path);
+        let output_dir = dir.path().join("out");
+
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+        ]);
+
+        assert!(!output.status.success(), "{path:?} was accepted");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid file path"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_post_hook_in_output_dir() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--post-hook",
+        "echo \"$MRPACK_NAME $MRPACK_VERSION\" > hook.txt",
+    ]);
+
+    assert_success(&output);
+    assert_eq!(
+        fs::read_to_string(output_dir.join("hook.txt")).unwrap(),
+        "Simple Pack 1.0.0\n"
+    );
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--post-hook",
+        "exit 3",
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn downloads_duplicated_entries_once() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("duplicated.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    let files = index["files"].as_array_mut().unwrap();
+    files.push(files[0].clone());
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Collapsed 1 duplicate"));
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+}
+
+#[test]
+fn deferred_hash_check_deletes_corrupted_files() {
+    let size = fs::metadata(fixture_dir("simple_pack").join("files/mods/example-mod.jar"))
+        .unwrap()
+        .len() as usize;
+    let server = MockServer::start(vec![("mods/example-mod.jar".to_string(), vec![b'x'; size])]);
+    let (dir, pack_path) = write_fixture_pack("simple_pack", &server.base_url);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--defer-hash-check",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+}
+
+#[test]
+fn shows_relative_paths_in_verify_and_hash_failures() {
+    let size = fs::metadata(fixture_dir("simple_pack").join("files/mods/example-mod.jar"))
+        .unwrap()
+        .len() as usize;
+    let server = MockServer::start(vec![("mods/example-mod.jar".to_string(), vec![b'x'; size])]);
+    let (dir, pack_path) = write_fixture_pack("simple_pack", &server.base_url);
+    let output_dir = dir.path().join("out");
+    let run_with = |extra: &[&str]| {
+        let mut args = vec![
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+        ];
+        args.extend(extra);
+        String::from_utf8(run(&args).stderr).unwrap()
+    };
+
+    let stderr = run_with(&["--keep-going-on-hash-fail", "--max-retries", "0"]);
+    assert!(
+        stderr.contains("File mods/example-mod.jar failed the hash check and was not installed")
+    );
+
+    let stderr = run_with(&["--verify"]);
+    assert!(stderr
+        .lines()
+        .any(|line| line == "Missing file mods/example-mod.jar"));
+
+    let stderr = run_with(&["--verify", "--absolute-paths"]);
+    let absolute = output_dir
+        .canonicalize()
+        .unwrap()
+        .join("mods/example-mod.jar");
+    assert!(stderr
+        .lines()
+        .any(|line| line == format!("Missing file {}", absolute.to_string_lossy())));
+}
+
+#[test]
+fn strips_leading_override_components() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("nested.mrpack");
+    Pack::with_index(&render_index("simple_pack", &server.base_url))
+        .entry("overrides/nested/config/example.toml", b"nested")
+        .entry("overrides/top.txt", b"dropped")
+        .write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--strip-components",
+        "1",
+    ]);
+
+    assert_success(&output);
+    assert_eq!(
+        fs::read(output_dir.join("config/example.toml")).unwrap(),
+        b"nested"
+    );
+    assert!(!output_dir.join("top.txt").exists());
+}
+
+#[test]
+fn validates_pack_without_downloading() {
+    let (server, _dir, pack_path) = serve_fixture_pack("simple_pack");
+
+    assert_success(&run(&[
+        "validate",
+        pack_path.to_str().unwrap(),
+        "--skip-host-check",
+    ]));
+    // The mock server is reached by IP address, which packs must not use
+    let output = run(&["validate", pack_path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FAIL: 1 problems"));
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+}
+
+#[test]
+fn creates_nested_output_dir() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("does/not/exist/yet");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+    assert!(output_dir.join("config/example.toml").is_file());
+}
+
+#[test]
+fn installs_pack_named_zip() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.zip");
+    Pack::from_fixture("simple_pack", &server.base_url).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+    assert!(output_dir.join("config/example.toml").is_file());
+}
+
+#[test]
+fn rejects_output_dir_that_is_a_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::from_fixture("simple_pack", "http://127.0.0.1:1").write(&pack_path);
+    let output_file = dir.path().join("out");
+    fs::write(&output_file, b"not a directory").unwrap();
+
+    for output_dir in [output_file.clone(), output_file.join("nested")] {
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+        ]);
+
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("is a file, not a directory"));
+    }
+}
+
+#[test]
+fn extracts_only_overrides() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+
+    // The mock server's IP address and the file count would fail the checks if anything was
+    // downloaded
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--only-overrides",
+        "--max-files",
+        "0",
+    ]);
+
+    assert_success(&output);
+    assert!(output_dir.join("config/example.toml").is_file());
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+}
+
+#[test]
+fn extracts_zstd_and_bzip2_entries() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("compressed.mrpack");
+    Pack::from_fixture("simple_pack", &server.base_url)
+        .entry("overrides/zstd.toml", b"compressed = \"zstd\"")
+        .entry("overrides/bzip2.toml", b"compressed = \"bzip2\"")
+        .write_with(&pack_path, |name| {
+            let compression = match name {
+                "overrides/zstd.toml" | "modrinth.index.json" => Compression::Zstd,
+                "overrides/bzip2.toml" => Compression::Bz,
+                _ => Compression::Deflate,
+            };
+            ZipEntryBuilder::new(name.into(), compression)
+        });
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert_eq!(
+        fs::read_to_string(output_dir.join("zstd.toml")).unwrap(),
+        "compressed = \"zstd\""
+    );
+    assert_eq!(
+        fs::read_to_string(output_dir.join("bzip2.toml")).unwrap(),
+        "compressed = \"bzip2\""
+    );
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+}
+
+#[test]
+fn reports_unsupported_compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("deflate64.mrpack");
+    Pack::from_fixture("simple_pack", "http://127.0.0.1:1")
+        .entry("overrides/deflate64.toml", b"compressed = true")
+        .write_with(&pack_path, |name| {
+            ZipEntryBuilder::new(name.into(), Compression::Stored)
+        });
+    // Mark the last entry as Deflate64 compressed in both its local and central directory header
+    let mut data = fs::read(&pack_path).unwrap();
+    let rfind = |data: &[u8], signature: &[u8]| {
+        data.windows(4)
+            .rposition(|window| window == signature)
+            .unwrap()
+    };
+    let local = rfind(&data, b"PK\x03\x04");
+    data[local + 8..local + 10].copy_from_slice(&9u16.to_le_bytes());
+    let central = rfind(&data, b"PK\x01\x02");
+    data[central + 10..central + 12].copy_from_slice(&9u16.to_le_bytes());
+    fs::write(&pack_path, data).unwrap();
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("uses Deflate64 compression, which is not supported"));
+}
+
+#[test]
+fn forces_files_in_and_out() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("client-only.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    index["files"][0]["env"]["server"] = "unsupported".into();
+    Pack::with_index(&index.to_string()).write(&pack_path);
+
+    let included_dir = dir.path().join("included");
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        included_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--target",
+        "server",
+        "--force-include",
+        "*.jar",
+    ]));
+    assert!(included_dir.join("mods/example-mod.jar").is_file());
+
+    let excluded_dir = dir.path().join("excluded");
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        excluded_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--force-include",
+        "**",
+        "--force-exclude",
+        "mods/example-*.jar",
+    ]));
+    assert!(!excluded_dir.join("mods/example-mod.jar").exists());
+}
+
+#[test]
+fn skips_junk_overrides() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("junk.mrpack");
+    Pack::from_fixture("simple_pack", &server.base_url)
+        .entry("overrides/config/.DS_Store", b"junk")
+        .entry("overrides/__MACOSX/config/._example.toml", b"junk")
+        .entry("overrides/Thumbs.db", b"junk")
+        .write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(output_dir.join("config/example.toml").is_file());
+    assert!(!output_dir.join("config/.DS_Store").exists());
+    assert!(!output_dir.join("__MACOSX").exists());
+    assert!(!output_dir.join("Thumbs.db").exists());
+}
+
+#[test]
+fn host_check_modes() {
+    let dir = tempfile::tempdir().unwrap();
+    let index = |downloads: &[&str]| {
+        serde_json::json!({
+            "formatVersion": 1,
+            "game": "minecraft",
+            "versionId": "1.0.0",
+            "name": "Mirrors",
+            "files": [{
+                "path": "mods/mod.jar",
+                "hashes": {"sha1": "00".repeat(20), "sha512": "00".repeat(64)},
+                "downloads": downloads,
+                "fileSize": 1
+            }],
+            "dependencies": {}
+        })
+        .to_string()
+    };
+    let mixed_path = dir.path().join("mixed.mrpack");
+    Pack::with_index(&index(&[
+        "https://cdn.modrinth.com/data/mod.jar",
+        "https://example.com/mod.jar",
+    ]))
+    .write(&mixed_path);
+    let bad_path = dir.path().join("bad.mrpack");
+    Pack::with_index(&index(&["https://example.com/mod.jar"])).write(&bad_path);
+    // Nothing is downloaded, only the host check matters
+    let check = |pack: &std::path::Path, mode: &str| {
+        run(&[
+            pack.to_str().unwrap(),
+            dir.path().join("out").to_str().unwrap(),
+            "--print-urls",
+            "--host-check",
+            mode,
+        ])
+    };
+
+    assert!(!check(&mixed_path, "any").status.success());
+    assert_success(&check(&mixed_path, "all"));
+    assert!(!check(&bad_path, "all").status.success());
+}
+
+#[test]
+fn installs_pack_from_tar_gz() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.tar.gz");
+    Pack::from_fixture("simple_pack", &server.base_url).write_tar_gz(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    let fixture = fixture_dir("simple_pack");
+    assert_eq!(
+        fs::read(output_dir.join("mods/example-mod.jar")).unwrap(),
+        fs::read(fixture.join("files/mods/example-mod.jar")).unwrap()
+    );
+    assert_eq!(
+        fs::read(output_dir.join("config/example.toml")).unwrap(),
+        fs::read(fixture.join("overrides/config/example.toml")).unwrap()
+    );
+}
+
+#[test]
+fn rejects_tar_with_huge_entry_size() {
+    let mut header = [0u8; 512];
+    header[..19].copy_from_slice(b"modrinth.index.json");
+    header[100..108].copy_from_slice(b"0000644\0");
+    // Binary size field holding the largest 64 bit number
+    header[124] = 0x80;
+    header[128..136].fill(0xff);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[148..156].fill(b' ');
+    let checksum: usize = header.iter().map(|&b| b as usize).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("huge.tar");
+    let mut data = header.to_vec();
+    data.resize(512 * 3, 0);
+    fs::write(&pack_path, data).unwrap();
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("truncated or corrupted at byte 0"));
+}
+
+#[test]
+fn installs_unpacked_pack_directory() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_dir = dir.path().join("unpacked");
+    for (name, data) in Pack::from_fixture("simple_pack", &server.base_url).entries {
+        let path = pack_dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_dir.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+    assert!(output_dir.join("config/example.toml").is_file());
+}
+
+#[test]
+fn retries_hash_mismatch_from_next_mirror() {
+    let fixture = fixture_dir("simple_pack");
+    let good = fs::read(fixture.join("files/mods/example-mod.jar")).unwrap();
+    let server = MockServer::start(vec![
+        ("stale/example-mod.jar".to_string(), vec![b'x'; good.len()]),
+        ("mods/example-mod.jar".to_string(), good.clone()),
+    ]);
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("mirrors.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    index["files"][0]["downloads"] = serde_json::json!([
+        format!("{}/stale/example-mod.jar", server.base_url),
+        format!("{}/mods/example-mod.jar", server.base_url),
+    ]);
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--max-retries",
+        "0",
+    ]);
+
+    assert_success(&output);
+    assert_eq!(
+        fs::read(output_dir.join("mods/example-mod.jar")).unwrap(),
+        good
+    );
+    assert_eq!(server.requests("stale/example-mod.jar"), 1);
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+}
+
+#[test]
+fn gives_up_on_bad_hash_when_other_mirror_is_missing() {
+    let fixture = fixture_dir("simple_pack");
+    let good = fs::read(fixture.join("files/mods/example-mod.jar")).unwrap();
+    let server = MockServer::start(vec![(
+        "stale/example-mod.jar".to_string(),
+        vec![b'x'; good.len()],
+    )]);
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("mirrors.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    index["files"][0]["downloads"] = serde_json::json!([
+        format!("{}/stale/example-mod.jar", server.base_url),
+        format!("{}/missing/example-mod.jar", server.base_url),
+    ]);
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("persisted after all retries"));
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+    // The missing mirror isn't asked again, the stale one once more than the 3 default retries
+    assert_eq!(server.requests("missing/example-mod.jar"), 1);
+    assert_eq!(server.requests("stale/example-mod.jar"), 4);
+}
+
+#[test]
+fn points_at_invalid_index_field() {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("invalid.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", "http://127.0.0.1:1")).unwrap();
+    index["files"][0]["hashes"]["sha1"] = "abc".into();
+    Pack::with_index(&index.to_string()).write(&pack_path);
+
+    let output = run(&["validate", pack_path.to_str().unwrap()]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("files[0].hashes.sha1: expected 40 hexadecimal characters"));
+}
+
+#[test]
+fn expands_environment_variables_in_output_dir() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    std::env::set_var("MRPACK_TEST_ROOT", dir.path());
+    std::env::set_var("MRPACK_TEST_ESCAPE", "../..");
+
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        "${MRPACK_TEST_ROOT}/servers/out",
+        "--skip-host-check",
+    ]));
+    assert!(dir
+        .path()
+        .join("servers/out/mods/example-mod.jar")
+        .is_file());
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        "$MRPACK_TEST_ROOT/$MRPACK_TEST_ESCAPE/out",
+        "--skip-host-check",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("contains `..`"));
+}
+
+#[test]
+fn side_overrides_win_over_common_ones() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("overrides.mrpack");
+    Pack::with_index(&render_index("simple_pack", &server.base_url))
+        .entry("overrides-client/options.txt", b"client")
+        .entry("overrides/options.txt", b"common")
+        .entry("overrides-server/options.txt", b"server")
+        .write(&pack_path);
+
+    for (target, expected) in [
+        ("client", "client"),
+        ("server", "server"),
+        ("both", "client"),
+    ] {
+        let output_dir = dir.path().join(target);
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+            "--target",
+            target,
+            "--verbose",
+        ]);
+
+        assert_success(&output);
+        assert_eq!(
+            fs::read_to_string(output_dir.join("options.txt")).unwrap(),
+            expected
+        );
+        assert!(String::from_utf8_lossy(&output.stdout).contains("replaces the one from"));
+    }
+}
+
+#[test]
+fn prints_download_urls() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+    let url = format!("{}/mods/example-mod.jar", server.base_url);
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--print-urls",
+    ]);
+    assert_success(&output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("{url}\n"));
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--print-urls",
+        "--json",
+    ]);
+    assert_success(&output);
+    let urls: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(urls, serde_json::json!({ "mods/example-mod.jar": [url] }));
+    assert!(!output_dir.exists());
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+}
+
+#[test]
+fn emits_aria2_input_file() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+    let input_path = dir.path().join("pack.aria2");
+
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--emit-aria2",
+        input_path.to_str().unwrap(),
+    ]));
+
+    let jar = fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap();
+    let expected = format!(
+        "{}/mods/example-mod.jar\n  dir={}\n  out=mods/example-mod.jar\n  checksum=sha-512={}\n",
+        server.base_url,
+        output_dir.to_str().unwrap(),
+        hex::encode(Sha512::digest(jar)),
+    );
+    assert_eq!(fs::read_to_string(&input_path).unwrap(), expected);
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+}
+
+#[test]
+fn detects_case_only_collisions() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("collisions.mrpack");
+    Pack::with_index(&render_index("simple_pack", &server.base_url))
+        .entry("overrides/mods/Example-Mod.jar", b"override")
+        .entry("overrides-client/config/example.toml", b"client")
+        .entry("overrides/config/example.toml", b"common")
+        .write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--case-sensitive-check",
+        "true",
+    ]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("mods/example-mod.jar and mods/Example-Mod.jar differ only in case"));
+    assert!(stderr.contains("Found 1 paths that differ only in case"));
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--case-sensitive-check",
+        "false",
+    ]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("differ only in case"));
+}
+
+#[test]
+fn installs_into_temp_dir() {
+    let (_server, _dir, pack_path) = serve_fixture_pack("simple_pack");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        "--temp-output",
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let output_dir = stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("Installed into "))
+        .map(std::path::PathBuf::from)
+        .unwrap();
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+    fs::remove_dir_all(output_dir).unwrap();
+}
+
+#[test]
+fn accepts_non_semver_dependency_versions() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("forge.mrpack");
+    let index = render_index("simple_pack", &server.base_url).replace(
+        "\"fabric-loader\": \"0.15.0\"",
+        "\"forge\": \"14.23.5.2860\"",
+    );
+    Pack::with_index(&index).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains(": 14.23.5.2860"));
+}
+
+#[test]
+fn skips_corrupted_override_entries() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("corrupted.mrpack");
+    let name = b"overrides/corrupted.toml";
+    Pack::from_fixture("simple_pack", &server.base_url)
+        .entry("overrides/corrupted.toml", b"corrupted = false")
+        .write(&pack_path);
+    // Turn the first deflate block into one of the reserved type, so decompressing it fails
+    let mut data = fs::read(&pack_path).unwrap();
+    let name_start = data
+        .windows(name.len())
+        .position(|window| window == name)
+        .unwrap();
+    let extra_len = u16::from_le_bytes([data[name_start - 2], data[name_start - 1]]);
+    data[name_start + name.len() + usize::from(extra_len)] = 0xff;
+    fs::write(&pack_path, data).unwrap();
+
+    let output_dir = dir.path().join("out");
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+    assert!(!output.status.success());
+    assert!(!output_dir.join("corrupted.toml").exists());
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--keep-going-on-extract-fail",
+    ]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("1 override files failed to extract and were skipped"));
+    assert!(!output_dir.join("corrupted.toml").exists());
+    assert!(output_dir.join("config/example.toml").is_file());
+}
+
+#[test]
+fn detects_override_crc_mismatch() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("corrupted.mrpack");
+    Pack::from_fixture("simple_pack", &server.base_url)
+        .entry("overrides/corrupted.toml", b"corrupted = false")
+        .write_with(&pack_path, |name| {
+            ZipEntryBuilder::new(name.into(), Compression::Stored)
+        });
+    // Flip a byte of the stored content so that it no longer matches its CRC
+    let mut data = fs::read(&pack_path).unwrap();
+    let content = data
+        .windows(17)
+        .position(|window| window == b"corrupted = false")
+        .unwrap();
+    data[content + 12] = b'F';
+    fs::write(&pack_path, data).unwrap();
+
+    let output_dir = dir.path().join("out");
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("its CRC doesn't match"));
+    assert!(!output_dir.join("corrupted.toml").exists());
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--keep-going-on-extract-fail",
+    ]);
+    assert_success(&output);
+    assert!(!output_dir.join("corrupted.toml").exists());
+    assert!(output_dir.join("config/example.toml").is_file());
+}
+
+#[test]
+fn installs_newer_format_with_unknown_fields() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("newer.mrpack");
+    let index = render_index("simple_pack", &server.base_url)
+        .replace(
+            "\"formatVersion\": 1,",
+            "\"formatVersion\": 2, \"icon\": \"icon.png\",",
+        )
+        .replace("\"fileSize\"", "\"projectId\": \"AANobbMI\", \"fileSize\"");
+    Pack::with_index(&index).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+    ]);
+
+    assert_success(&output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("uses format version 2"));
+    assert!(stderr.contains("unknown fields in the modpack index: files[].projectId, icon"));
+    assert!(output_dir.join("mods/example-mod.jar").is_file());
+
+    // Validating doesn't fail the pack the installer accepts
+    let output = run(&["validate", pack_path.to_str().unwrap(), "--skip-host-check"]);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("warning: formatVersion: version 2 is newer than the supported version 1"));
+}
+
+#[test]
+fn prints_pack_hashes() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+
+    let output = run(&["hash", pack_path.to_str().unwrap(), "--json"]);
+    assert_success(&output);
+    let hashes: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let expected = hex::encode(Sha512::digest(fs::read(&pack_path).unwrap()));
+    assert_eq!(hashes["sha512"], expected.as_str());
+
+    let sha256 = hashes["sha256"].as_str().unwrap();
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--skip-host-check",
+        "--verify-pack",
+        sha256,
+    ]));
+}
+
+#[test]
+fn dumps_raw_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("broken.mrpack");
+    let index = r#"{"formatVersion": 1, "files": [}"#;
+    Pack::with_index(index).write(&pack_path);
+
+    let output = run(&["dump-index", pack_path.to_str().unwrap()]);
+    assert_success(&output);
+    assert_eq!(output.stdout, index.as_bytes());
+
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::from_fixture("simple_pack", "http://127.0.0.1:1").write(&pack_path);
+    let dump_path = dir.path().join("index.json");
+    assert_success(&run(&[
+        "dump-index",
+        pack_path.to_str().unwrap(),
+        "--pretty",
+        "--output",
+        dump_path.to_str().unwrap(),
+    ]));
+    let dumped = fs::read_to_string(&dump_path).unwrap();
+    assert!(dumped.contains("\n  \"formatVersion\": 1,\n"));
+    let index: serde_json::Value = serde_json::from_str(&dumped).unwrap();
+    assert_eq!(index["name"], "Simple Pack");
+}
+
+#[test]
+fn writes_log_file() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let log_path = dir.path().join("install.log");
+
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--skip-host-check",
+        "--log-file",
+        log_path.to_str().unwrap(),
+    ]));
+
+    let log = fs::read_to_string(&log_path).unwrap();
+    // Logged even without --verbose
+    let download_line = format!(
+        "Downloading mods/example-mod.jar from {}/mods/example-mod.jar",
+        server.base_url
+    );
+    assert!(log.lines().any(|line| line.ends_with(&download_line)));
+    assert!(log.contains("Extracting config/example.toml"));
+    assert!(log.lines().all(|line| line.starts_with('[')));
+}
+
+#[test]
+fn installs_pack_without_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("overrides-only.mrpack");
+    let index = r#"{
+        "formatVersion": 1,
+        "game": "minecraft",
+        "versionId": "1.0.0",
+        "name": "Overrides Only",
+        "files": [],
+        "dependencies": { "minecraft": "1.20.1" }
+    }"#;
+    Pack::with_index(index)
+        .entry("overrides/config/example.toml", b"enabled = true")
+        .write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[pack_path.to_str().unwrap(), output_dir.to_str().unwrap()]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("No files to download, only extracting overrides"));
+    assert_eq!(
+        fs::read_to_string(output_dir.join("config/example.toml")).unwrap(),
+        "enabled = true"
+    );
+}
+
+#[test]
+fn writes_install_summary() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+    let summary_path = dir.path().join("summary.json");
+    let install = || {
+        assert_success(&run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+            "--summary-json",
+            summary_path.to_str().unwrap(),
+        ]));
+        serde_json::from_slice::<serde_json::Value>(&fs::read(&summary_path).unwrap()).unwrap()
+    };
+
+    let summary = install();
+    assert_eq!(summary["name"], "Simple Pack");
+    assert_eq!(summary["dependencies"]["fabric-loader"], "0.15.0");
+    assert_eq!(summary["files"]["mods/example-mod.jar"], "downloaded");
+    assert_eq!(summary["overrides"]["config/example.toml"], "overrides");
+    let jar = fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap();
+    assert_eq!(summary["bytes_downloaded"], jar.len());
+
+    let summary = install();
+    assert_eq!(summary["files"]["mods/example-mod.jar"], "skipped");
+    assert_eq!(summary["bytes_downloaded"], 0);
+    assert!(summary["error"].is_null());
+}
+
+#[test]
+fn writes_summary_of_failed_install() {
+    let size = fs::metadata(fixture_dir("simple_pack").join("files/mods/example-mod.jar"))
+        .unwrap()
+        .len() as usize;
+    let corrupting =
+        MockServer::start(vec![("mods/example-mod.jar".to_string(), vec![b'x'; size])]);
+    let missing = MockServer::start(Vec::new());
+    let dir = tempfile::tempdir().unwrap();
+    let summary_path = dir.path().join("summary.json");
+    let install = |server: &MockServer| {
+        let pack_path = dir.path().join("simple.mrpack");
+        Pack::from_fixture("simple_pack", &server.base_url).write(&pack_path);
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            dir.path().join("out").to_str().unwrap(),
+            "--skip-host-check",
+            "--max-retries",
+            "0",
+            "--summary-json",
+            summary_path.to_str().unwrap(),
+        ]);
+        assert!(!output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&fs::read(&summary_path).unwrap()).unwrap()
+    };
+
+    let summary = install(&corrupting);
+    assert_eq!(summary["files"]["mods/example-mod.jar"], "failed");
+    assert!(summary["error"]
+        .as_str()
+        .unwrap()
+        .contains("persisted after all retries"));
+    assert_eq!(summary["overrides"], serde_json::json!({}));
+
+    let summary = install(&missing);
+    assert_eq!(summary["files"]["mods/example-mod.jar"], "not_installed");
+    assert!(summary["error"]
+        .as_str()
+        .unwrap()
+        .contains("Every mirror of"));
+}
+
+#[test]
+fn verifies_files_against_modrinth() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let jar = fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap();
+    let sha1 = hex::encode(sha1::Sha1::digest(&jar));
+    let sha512 = hex::encode(Sha512::digest(&jar));
+    let version = serde_json::json!({
+        "id": "version",
+        "project_id": "project",
+        "files": [{
+            "hashes": { "sha1": sha1, "sha512": sha512 },
+            "url": "https://cdn.modrinth.com/data/project/versions/version/example-mod.jar",
+            "filename": "example-mod.jar",
+        }],
+    });
+    let api = MockServer::start(vec![(
+        format!("v2/version_file/{sha512}?algorithm=sha512"),
+        version.to_string().into_bytes(),
+    )]);
+    let empty_api = MockServer::start(Vec::new());
+    let install = |pack_path: &std::path::Path, api: &MockServer| {
+        run(&[
+            pack_path.to_str().unwrap(),
+            dir.path().join("out").to_str().unwrap(),
+            "--skip-host-check",
+            "--verify-upstream",
+            "--modrinth-api",
+            &api.base_url,
+        ])
+    };
+
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::from_fixture("simple_pack", &server.base_url).write(&pack_path);
+    let output = install(&pack_path, &api);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 published"));
+
+    // Files on other hosts don't have to be published
+    let output = install(&pack_path, &empty_api);
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not published on Modrinth"));
+
+    let cdn_pack_path = dir.path().join("cdn.mrpack");
+    Pack::from_fixture("simple_pack", "https://cdn.modrinth.com/data").write(&cdn_pack_path);
+    let output = install(&cdn_pack_path, &empty_api);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("1 files don't match any file published on Modrinth"));
+
+    // Checking upstream and downloading share one client
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--skip-host-check",
+        "--verify-upstream",
+        "--modrinth-api",
+        &api.base_url,
+        "--danger-accept-invalid-certs",
+    ]);
+    assert_success(&output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr)
+            .matches("TLS certificates are not checked")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn only_extracts_changed_overrides() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::from_fixture("simple_pack", &server.base_url)
+        .entry("overrides/options.txt", b"fov:90")
+        .write(&pack_path);
+    let output_dir = dir.path().join("out");
+    let install = || {
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+            "--only-changed-overrides",
+        ]);
+        assert_success(&output);
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    install();
+    // Same size, different content
+    fs::write(output_dir.join("options.txt"), "fov:70").unwrap();
+    let stdout = install();
+
+    assert!(stdout.contains("Skipping config/example.toml, it is unchanged"));
+    assert!(stdout.contains("Extracting options.txt"));
+    assert_eq!(
+        fs::read_to_string(output_dir.join("options.txt")).unwrap(),
+        "fov:90"
+    );
+}
+
+#[test]
+fn extracts_overrides_concurrently() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    let mut pack = Pack::from_fixture("simple_pack", &server.base_url);
+    for i in 0..32 {
+        pack = pack.entry(
+            &format!("overrides/config/{i}.txt"),
+            i.to_string().as_bytes(),
+        );
+    }
+    pack.write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--extract-jobs",
+        "8",
+    ]);
+
+    assert_success(&output);
+    for i in 0..32 {
+        assert_eq!(
+            fs::read_to_string(output_dir.join(format!("config/{i}.txt"))).unwrap(),
+            i.to_string()
+        );
+    }
+}
+
+#[test]
+fn installs_files_outside_content_folders() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("unusual.mrpack");
+    let index = render_index("simple_pack", &server.base_url).replace(
+        "\"path\": \"mods/example-mod.jar\"",
+        "\"path\": \"config/loader/example-mod.jar\"",
+    );
+    Pack::with_index(&index).write(&pack_path);
+    let output_dir = dir.path().join("out");
+    let summary_path = dir.path().join("summary.json");
+    let install = || {
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+            "--summary-json",
+            summary_path.to_str().unwrap(),
+        ]);
+        assert_success(&output);
+        output
+    };
+
+    let output = install();
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Warning: config/loader/example-mod.jar is installed outside of the usual"));
+    assert!(output_dir.join("config/loader/example-mod.jar").is_file());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("config: 1"));
+
+    // The existing file is recognized on the next install
+    install();
+    let summary: serde_json::Value =
+        serde_json::from_slice(&fs::read(&summary_path).unwrap()).unwrap();
+    assert_eq!(summary["files"]["config/loader/example-mod.jar"], "skipped");
+}
+
+#[test]
+fn skips_files_over_max_file_size() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+    let summary_path = dir.path().join("summary.json");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--max-file-size",
+        "1",
+        "--summary-json",
+        summary_path.to_str().unwrap(),
+    ]);
+
+    assert_success(&output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning: skipping mods/example-mod.jar"));
+    assert!(stderr.contains(&format!(
+        "mods/example-mod.jar: {}/mods/example-mod.jar",
+        server.base_url
+    )));
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+    assert!(output_dir.join("config/example.toml").is_file());
+    let summary: serde_json::Value =
+        serde_json::from_slice(&fs::read(&summary_path).unwrap()).unwrap();
+    assert_eq!(summary["files"]["mods/example-mod.jar"], "oversized");
+}
+
+#[test]
+fn keeps_mirror_auth_off_plain_http() {
+    let (server, _dir, pack_path) = serve_fixture_pack("simple_pack");
+    let install = |auth: &str| {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.path().to_str().unwrap(),
+            "--skip-host-check",
+            "--mirror-auth",
+            auth,
+        ]);
+        assert_success(&output);
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    // Without the port the mock server isn't the same host
+    let stderr = install("127.0.0.1=user:secret");
+    assert!(!stderr.contains("--mirror-auth"), "{stderr}");
+    assert_eq!(server.authorization("mods/example-mod.jar"), None);
+
+    let host = server.base_url.trim_start_matches("http://");
+    let stderr = install(&format!("{host}=user:secret:with:colons"));
+    assert!(
+        stderr.contains(&format!(
+            "Warning: not sending the --mirror-auth credentials for {host} over plain http"
+        )),
+        "{stderr}"
+    );
+    assert_eq!(server.authorization("mods/example-mod.jar"), None);
+}
+
+#[test]
+fn prints_required_java_version() {
+    let dir = tempfile::tempdir().unwrap();
+    for (minecraft, java) in [
+        ("1.12.2", 8),
+        ("1.17.1", 16),
+        ("1.20", 17),
+        ("1.20.4", 17),
+        ("1.20.5", 21),
+        ("1.21.1", 21),
+    ] {
+        let pack_path = dir.path().join(format!("{minecraft}.mrpack"));
+        let index = render_index("simple_pack", "http://127.0.0.1:1").replace(
+            "\"minecraft\": \"1.20.1\"",
+            &format!("\"minecraft\": \"{minecraft}\""),
+        );
+        Pack::with_index(&index).write(&pack_path);
+
+        let output = run(&[
+            pack_path.to_str().unwrap(),
+            dir.path().join(minecraft).to_str().unwrap(),
+            "--skip-host-check",
+            "--only-overrides",
+        ]);
+
+        assert_success(&output);
+        assert!(
+            String::from_utf8_lossy(&output.stdout)
+                .contains(&format!("This pack needs Java {java}")),
+            "Minecraft {minecraft} should need Java {java}"
+        );
+    }
+}
+
+#[test]
+fn confirm_hosts_is_strict_without_terminal() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--confirm-hosts",
+    ]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("IP addresses are not allowed"));
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+}
+
+#[test]
+fn sends_github_token_only_to_github_over_https() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        dir.path().join("out").to_str().unwrap(),
+        "--skip-host-check",
+        "--github-token",
+        "secret",
+    ]);
+    assert_success(&output);
+    assert_eq!(server.authorization("mods/example-mod.jar"), None);
+
+    // Plain http to GitHub, which the mock server receives as a proxy
+    let index = render_index("simple_pack", "http://github.com");
+    let pack_path = dir.path().join("github.mrpack");
+    Pack::with_index(&index).write(&pack_path);
+    let output = run_with_env(
+        &[
+            pack_path.to_str().unwrap(),
+            dir.path().join("github").to_str().unwrap(),
+            "--github-token",
+            "secret",
+        ],
+        &[("http_proxy", &server.base_url), ("no_proxy", "")],
+    );
+    assert_success(&output);
+    assert_eq!(server.requests("mods/example-mod.jar"), 2);
+    assert_eq!(server.authorization("mods/example-mod.jar"), None);
+}
+
+#[test]
+fn replays_saved_selection() {
+    let server = MockServer::start(vec![
+        (
+            "mods/example-mod.jar".to_string(),
+            fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap(),
+        ),
+        (
+            "mods/other-mod.jar".to_string(),
+            fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap(),
+        ),
+    ]);
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    // Both files are optional, only one of them is chosen
+    index["files"][0]["env"]["client"] = "optional".into();
+    add_file_copy(&mut index, &server.base_url, "mods/other-mod.jar");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("optional.mrpack");
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let selection_path = dir.path().join("selection.json");
+    fs::write(&selection_path, r#"{"include": ["mods/other-mod.jar"]}"#).unwrap();
+    let saved_path = dir.path().join("saved.json");
+
+    let first_dir = dir.path().join("first");
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        first_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--selection",
+        selection_path.to_str().unwrap(),
+        "--save-selection",
+        saved_path.to_str().unwrap(),
+    ]));
+    let second_dir = dir.path().join("second");
+    assert_success(&run(&[
+        pack_path.to_str().unwrap(),
+        second_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--selection",
+        saved_path.to_str().unwrap(),
+    ]));
+
+    for output_dir in [first_dir, second_dir] {
+        assert!(!output_dir.join("mods/example-mod.jar").exists());
+        assert!(output_dir.join("mods/other-mod.jar").is_file());
+    }
+    assert_eq!(server.requests("mods/example-mod.jar"), 0);
+}
+
+#[test]
+fn removes_partial_file_on_global_timeout() {
+    let server = MockServer::start_stalling(read_dir_recursive(
+        &fixture_dir("simple_pack").join("files"),
+    ));
+    let (dir, pack_path) = write_fixture_pack("simple_pack", &server.base_url);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--global-timeout",
+        "1",
+    ]);
+
+    assert!(!output.status.success());
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+    assert!(!output_dir.join("mods/example-mod.jar").exists());
+}
+
+#[test]
+fn kills_post_hook_on_global_timeout() {
+    let (_server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+
+    // The output is only complete once the orphaned sleep exits, after the hook would have
+    // created the file
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--global-timeout",
+        "1",
+        "--post-hook",
+        "sleep 3; touch late.txt",
+    ]);
+
+    assert_eq!(output.status.code(), Some(124));
+    assert!(!output_dir.join("late.txt").exists());
+}
+
+#[test]
+fn moves_on_to_next_mirror_on_file_timeout() {
+    let stalling = MockServer::start_stalling(read_dir_recursive(
+        &fixture_dir("simple_pack").join("files"),
+    ));
+    let server = MockServer::for_fixture("simple_pack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &stalling.base_url)).unwrap();
+    index["files"][0]["downloads"]
+        .as_array_mut()
+        .unwrap()
+        .push(format!("{}/mods/example-mod.jar", server.base_url).into());
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    let output = run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--file-timeout",
+        "1",
+    ]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Timed out after 1 seconds"));
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+    assert_eq!(
+        fs::read(output_dir.join("mods/example-mod.jar")).unwrap(),
+        fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap()
+    );
+}
+
+#[test]
+fn trusts_hashes_over_wrong_file_size() {
+    let server = MockServer::for_fixture("simple_pack");
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("simple.mrpack");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    index["files"][0]["fileSize"] = (index["files"][0]["fileSize"].as_u64().unwrap() + 1).into();
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+    let install = |extra: &[&str]| {
+        let mut args = vec![
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+        ];
+        args.extend_from_slice(extra);
+        assert_success(&run(&args));
+    };
+
+    install(&[]);
+    install(&["--trust-hashes"]);
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+    install(&[]);
+    assert_eq!(server.requests("mods/example-mod.jar"), 2);
+}
+
+#[test]
+fn skip_check_size_keeps_same_size_files() {
+    let (server, dir, pack_path) = serve_fixture_pack("simple_pack");
+    let output_dir = dir.path().join("out");
+    let install = |skip_check: &str| {
+        assert_success(&run(&[
+            pack_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            "--skip-host-check",
+            "--skip-check",
+            skip_check,
+        ]));
+    };
+    install("full");
+    let path = output_dir.join("mods/example-mod.jar");
+    let size = fs::metadata(&path).unwrap().len() as usize;
+    fs::write(&path, vec![b'x'; size]).unwrap();
+
+    install("size");
+    assert_eq!(server.requests("mods/example-mod.jar"), 1);
+    install("full");
+    assert_eq!(server.requests("mods/example-mod.jar"), 2);
+}
+
+#[test]
+fn per_host_jobs_leave_other_hosts_free() {
+    let files = || {
+        let jar = fs::read(fixture_dir("simple_pack").join("files/mods/example-mod.jar")).unwrap();
+        ["mods/first.jar", "mods/second.jar", "mods/other.jar"]
+            .map(|path| (path.to_string(), jar.clone()))
+            .to_vec()
+    };
+    let stuck = MockServer::start_stalling(files());
+    let server = MockServer::start(files());
+    let mut index: serde_json::Value =
+        serde_json::from_str(&render_index("simple_pack", &server.base_url)).unwrap();
+    for path in ["mods/first.jar", "mods/second.jar", "mods/other.jar"] {
+        add_file_copy(&mut index, &server.base_url, path);
+    }
+    let files = index["files"].as_array_mut().unwrap();
+    files.remove(0);
+    // Both mirrors of the first two files, the stuck one first. The third is only on the other.
+    for file in &mut files[..2] {
+        let path = file["path"].as_str().unwrap().to_string();
+        file["downloads"] = serde_json::json!([
+            format!("{}/{path}", stuck.base_url),
+            format!("{}/{path}", server.base_url),
+        ]);
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let pack_path = dir.path().join("mirrors.mrpack");
+    Pack::with_index(&index.to_string()).write(&pack_path);
+    let output_dir = dir.path().join("out");
+
+    run(&[
+        pack_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        "--skip-host-check",
+        "--jobs",
+        "2",
+        "--per-host-jobs",
+        "1",
+        "--global-timeout",
+        "3",
+    ]);
+
+    // One file is stuck on the first host, the other one moves on to the free host instead of
+    // waiting, and the file on the free host isn't held up by either
+    assert_eq!(
+        stuck.requests("mods/first.jar") + stuck.requests("mods/second.jar"),
+        1
+    );
+    assert_eq!(
+        server.requests("mods/first.jar") + server.requests("mods/second.jar"),
+        1
+    );
+    assert_eq!(server.requests("mods/other.jar"), 1);
+    assert!(output_dir.join("mods/other.jar").is_file());
+}